@@ -0,0 +1,238 @@
+use crate::{db::User, err, err::raise_err, state::AppState, storage::Record, utils};
+use actix_web::{
+    dev::{Payload, ServiceRequest},
+    http::header::{self, HeaderMap},
+    post, web, FromRequest, HttpMessage, HttpRequest, Responder, Result,
+};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::future::{ready, Ready};
+
+// Password hash of a user, kept apart from User so it never leaves the server
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Credential {
+    pub(crate) id: i32,
+    hash: String,
+}
+
+impl Record for Credential {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl Credential {
+    pub fn new(id: i32, password: &str) -> Self {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string();
+        Self { id, hash }
+    }
+    fn verify(&self, password: &str) -> bool {
+        PasswordHash::new(&self.hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+}
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    utils::hex(&bytes)
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    ProblemSetter,
+    #[default]
+    Contestant,
+}
+
+// Whoever sent the request
+#[derive(Clone)]
+pub struct Viewer {
+    pub role: Role,
+    // Set when a login token is presented
+    pub user_id: Option<i32>,
+}
+
+impl Viewer {
+    pub fn root() -> Self {
+        Self {
+            role: Role::Admin,
+            user_id: None,
+        }
+    }
+    pub fn admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+    // Admins and the owner of a job may read its source
+    pub fn owns(&self, user_id: i32) -> bool {
+        self.admin() || self.user_id == Some(user_id)
+    }
+
+    // Services outside the routes (e.g. /internal/exit) are not behind identify
+    fn of(req: &HttpRequest) -> Self {
+        let viewer = req.extensions().get::<Viewer>().cloned();
+        viewer.unwrap_or_else(|| Self::resolve(req.app_data().unwrap(), req.headers()))
+    }
+    fn resolve(state: &web::Data<AppState>, headers: &HeaderMap) -> Self {
+        let server = &state.conf.server;
+        let token = bearer(headers);
+        let user_id = token.and_then(|token| state.sessions.lock().unwrap().get(token).copied());
+        let role = user_id.and_then(|id| state.read(|db| db.user(id).map(|user| user.role)));
+        let role = match (&server.admin_token, token) {
+            (Some(admin_token), Some(token)) if token == admin_token => Role::Admin,
+            // Logged in users act with their own role
+            _ if user_id.is_some() => role.unwrap_or_default(),
+            // Without any credentials, only when the server is configured open
+            (_, None) if server.open => Role::Admin,
+            _ => Role::Contestant,
+        };
+        Self { role, user_id }
+    }
+}
+
+// Middleware run before every route: finds out who is calling so the extractors
+// below only have to check the role
+pub fn identify(req: &ServiceRequest) {
+    let viewer = Viewer::resolve(req.app_data().unwrap(), req.headers());
+    req.extensions_mut().insert(viewer);
+}
+
+impl FromRequest for Viewer {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Viewer::of(req)))
+    }
+}
+
+// Rejects the request unless it comes from an admin
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(match Viewer::of(req).admin() {
+            true => Ok(Admin),
+            false => {
+                Err(err::Error::new(err::ErrorKind::ErrForbidden, "Admin only.".to_string()).into())
+            }
+        })
+    }
+}
+
+// Rejects the request unless it comes from a judge-worker
+pub struct Worker;
+
+impl FromRequest for Worker {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let state: &web::Data<AppState> = req.app_data().unwrap();
+        let allowed = match &state.conf.judge.worker_token {
+            Some(token) => bearer(req.headers()) == Some(token.as_str()),
+            None => Viewer::of(req).admin(),
+        };
+        ready(match allowed {
+            true => Ok(Worker),
+            false => Err(err::Error::new(
+                err::ErrorKind::ErrForbidden,
+                "Judge workers only.".to_string(),
+            )
+            .into()),
+        })
+    }
+}
+
+// Rejects the request unless it comes from an admin or a problem setter
+pub struct Setter;
+
+impl FromRequest for Setter {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(match Self::allows(Viewer::of(req).role) {
+            true => Ok(Setter),
+            false => Err(err::Error::new(
+                err::ErrorKind::ErrForbidden,
+                "Problem setters only.".to_string(),
+            )
+            .into()),
+        })
+    }
+}
+
+impl Setter {
+    pub fn allows(role: Role) -> bool {
+        matches!(role, Role::Admin | Role::ProblemSetter)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Login {
+    name: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+struct Session {
+    user: User,
+    token: String,
+}
+
+#[post("/login")]
+pub async fn login(body: web::Json<Login>, state: web::Data<AppState>) -> Result<impl Responder> {
+    let found = state.read(|db| {
+        let user = db.users.iter().find(|user| user.name == body.name)?;
+        Some((user.clone(), db.credential(user.id)?.clone()))
+    });
+    // Hashing is slow, keep it out of the lock
+    let user = found.and_then(|(user, cred)| cred.verify(&body.password).then_some(user));
+    let user = match user {
+        Some(user) => user,
+        None => raise_err!(
+            err::ErrorKind::ErrUnauthorized,
+            "Wrong user name or password."
+        ),
+    };
+    let token = new_token();
+    state
+        .sessions
+        .lock()
+        .unwrap()
+        .insert(token.clone(), user.id);
+    Ok(web::Json(Session { user, token }))
+}
+
+#[post("/logout")]
+pub async fn logout(req: HttpRequest, state: web::Data<AppState>) -> Result<impl Responder> {
+    if let Some(token) = bearer(req.headers()) {
+        state.sessions.lock().unwrap().remove(token);
+    }
+    Ok(web::Json(()))
+}
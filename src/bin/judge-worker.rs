@@ -0,0 +1,125 @@
+use clap::Parser;
+use oj::judge::{judge_safe, Task};
+use reqwest::{
+    blocking::Client,
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    StatusCode,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::{thread, time::Duration};
+
+#[derive(Parser, Debug)]
+#[clap(
+    version,
+    about = "Pull jobs from an OJ server and judge them",
+    long_about = "Pull jobs from an OJ server and judge them.\n\n\
+        Tasks name case, checker and data files by their paths on the server, \
+        so the worker must see the same files at the same paths, e.g. by running \
+        in the server's working directory or mounting its data directory there."
+)]
+struct Args {
+    /// Address of the OJ server
+    #[clap(short, long, default_value = "http://127.0.0.1:12345")]
+    server: String,
+
+    /// Name reported to the server, defaults to worker-<pid>
+    #[clap(short, long)]
+    name: Option<String>,
+
+    /// Seconds between two heartbeats
+    #[clap(long, default_value_t = 2)]
+    heartbeat: u64,
+
+    /// Worker token of the server, or the admin token if it has none
+    #[clap(short, long)]
+    token: Option<String>,
+}
+
+fn fetch(client: &Client, prefix: &str) -> reqwest::Result<Option<Task>> {
+    let resp = client
+        .post(format!("{}/fetch", prefix))
+        .send()?
+        .error_for_status()?;
+    match resp.status() {
+        StatusCode::NO_CONTENT => Ok(None),
+        _ => resp.json().map(Some),
+    }
+}
+
+fn main() {
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    let args = Args::parse();
+    let name = args
+        .name
+        .unwrap_or_else(|| format!("worker-{}", std::process::id()));
+    let prefix = format!(
+        "{}/internal/workers/{}",
+        args.server.trim_end_matches('/'),
+        name
+    );
+    let interval = Duration::from_secs(args.heartbeat);
+    let mut headers = HeaderMap::new();
+    if let Some(token) = &args.token {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token)).expect("bad token");
+        headers.insert(AUTHORIZATION, value);
+    }
+    // Every call carries the token
+    let client = Client::builder().default_headers(headers).build().unwrap();
+    log::info!("{} polling {}", name, args.server);
+
+    loop {
+        let task = match fetch(&client, &prefix) {
+            Ok(Some(task)) => task,
+            Ok(None) => {
+                thread::sleep(Duration::from_millis(500));
+                continue;
+            }
+            Err(err) => {
+                log::warn!("fetch failed: {}", err);
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+        };
+        let job_url = format!("{}/jobs/{}", prefix, task.job_id);
+        log::info!("judging job {}", task.job_id);
+
+        let done = Arc::new(AtomicBool::new(false));
+        let beat = {
+            let (client, url, done) = (
+                client.clone(),
+                format!("{}/heartbeat", job_url),
+                done.clone(),
+            );
+            thread::spawn(move || loop {
+                thread::park_timeout(interval);
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                if let Err(err) = client.post(&url).send().and_then(|r| r.error_for_status()) {
+                    log::warn!("heartbeat failed: {}", err);
+                }
+            })
+        };
+        let cases = judge_safe(
+            &task.submission,
+            &task.language,
+            &task.problem,
+            task.pretests_only,
+        );
+        done.store(true, Ordering::Relaxed);
+        beat.thread().unpark();
+        beat.join().unwrap();
+
+        let resp = client
+            .post(format!("{}/report", job_url))
+            .json(&cases)
+            .send()
+            .and_then(|r| r.error_for_status());
+        if let Err(err) = resp {
+            log::warn!("report of job {} failed: {}", task.job_id, err);
+        }
+    }
+}
@@ -1,7 +1,6 @@
-use crate::err;
-use clap::Parser;
+use crate::{db::nul_id, err, storage::Record};
+use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
 fn default_bind_address() -> String {
     "127.0.0.1".to_string()
@@ -9,6 +8,21 @@ fn default_bind_address() -> String {
 fn default_bind_port() -> u16 {
     12345
 }
+fn default_local_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+fn default_heartbeat_timeout() -> u64 {
+    10
+}
+fn default_admin_name() -> String {
+    "root".to_string()
+}
+fn default_data_dir() -> String {
+    "oj-problems".to_string()
+}
+fn default_storage_path() -> String {
+    "oj-data".to_string()
+}
 
 #[derive(Parser, Debug)]
 #[clap(version, about, long_about = None)]
@@ -20,6 +34,20 @@ pub struct Args {
     /// Flush data when started
     #[clap(short, long = "flush-data")]
     flush: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Import a problem package from a directory, then exit
+    Import {
+        dir: String,
+        /// Problem to replace, a new one by default
+        #[clap(long)]
+        id: Option<i32>,
+    },
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -30,20 +58,47 @@ pub enum ProblemType {
     Spj,
     DynamicRanking,
 }
-#[derive(Clone, Serialize, Deserialize, Debug)]
-#[serde(rename_all = "snake_case")]
-pub enum MiscType {
-    SpecialJudge(Vec<String>),
-    Packing(Vec<Vec<i32>>),
-    DynamicRankingRatio(i32),
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Misc {
+    pub special_judge: Option<Vec<String>>,
+    pub packing: Option<Vec<Vec<i32>>>,
+    pub dynamic_ranking_ratio: Option<f64>,
+    // Max number of cases of one job judged at the same time
+    pub parallel_cases: Option<usize>,
+    // Ids of the cases judged during contests with pretests
+    pub pretests: Option<Vec<i32>>,
+    // Ids of the cases shown to everyone with the problem
+    pub samples: Option<Vec<i32>>,
+}
+// Markdown, with LaTeX math between $ and $ or $$ and $$. Images are files
+// of the problem's statement directory, referred to by their name.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Statement {
+    pub legend: String,
+    pub input_format: String,
+    pub output_format: String,
+    pub notes: String,
 }
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Problem {
+    // Left out when posting a new problem
+    #[serde(default = "nul_id")]
     pub id: i32,
     pub name: String,
     pub r#type: ProblemType,
-    pub misc: HashMap<String, MiscType>,
+    #[serde(default)]
+    pub misc: Misc,
     pub cases: Vec<Case>,
+    #[serde(default)]
+    pub statement: Option<Statement>,
+}
+
+impl Record for Problem {
+    fn id(&self) -> i32 {
+        self.id
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -52,6 +107,77 @@ pub struct Server {
     pub bind_address: String,
     #[serde(default = "default_bind_port")]
     pub bind_port: u16,
+    // Bearer token of the admin
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    // Requests carrying no token at all are treated as admin, as before roles
+    // existed. Only for trusted setups, logged in users keep their own role.
+    #[serde(default)]
+    pub open: bool,
+    // New users must set a password. Jobs are always posted by logged in users
+    // or admins.
+    #[serde(default)]
+    pub require_auth: bool,
+}
+
+// User 0, created on first start
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct BootstrapAdmin {
+    #[serde(default = "default_admin_name")]
+    pub name: String,
+    // Without one the admin can't log in, only the admin token can act as admin
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl Default for BootstrapAdmin {
+    fn default() -> Self {
+        Self {
+            name: default_admin_name(),
+            password: None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Judge {
+    // Set to 0 to leave all jobs to judge-worker processes
+    #[serde(default = "default_local_workers")]
+    pub local_workers: usize,
+    // Seconds before a silent remote worker loses its job
+    #[serde(default = "default_heartbeat_timeout")]
+    pub heartbeat_timeout: u64,
+    // Bearer token judge-workers must present, without it only admins may act as workers
+    #[serde(default)]
+    pub worker_token: Option<String>,
+}
+
+impl Default for Judge {
+    fn default() -> Self {
+        Self {
+            local_workers: default_local_workers(),
+            heartbeat_timeout: default_heartbeat_timeout(),
+            worker_token: None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StorageConf {
+    Memory,
+    File {
+        #[serde(default = "default_storage_path")]
+        path: String,
+    },
+}
+
+impl Default for StorageConf {
+    fn default() -> Self {
+        Self::File {
+            path: default_storage_path(),
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -73,26 +199,33 @@ pub struct Language {
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Conf {
     pub server: Server,
+    #[serde(default)]
+    pub admin: BootstrapAdmin,
+    #[serde(default)]
+    pub judge: Judge,
+    #[serde(default)]
+    pub storage: StorageConf,
+    // Uploaded test data, one directory per problem
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
     pub problems: Vec<Problem>,
     pub languages: Vec<Language>,
+    #[serde(skip)]
+    pub flush_data: bool,
+    #[serde(skip)]
+    pub command: Option<Command>,
 }
 
 impl Conf {
     pub fn parse() -> std::io::Result<Self> {
         let args = Args::parse();
         let json = std::fs::read_to_string(&args.config)?;
-        let conf = serde_json::from_str(&json).unwrap();
-        Ok(conf)
-    }
-
-    pub fn check_prob_and_get(&self, id: i32) -> Result<&Problem, err::Error> {
-        for prob in self.problems.iter() {
-            if id == prob.id {
-                log::info!("id: {}, prob_id: {}", id, prob.id);
-                return Ok(&prob);
-            }
-        }
-        err::raise_err!(err::ErrorKind::ErrNotFound, "")
+        let conf: Self = serde_json::from_str(&json).unwrap();
+        Ok(Self {
+            flush_data: args.flush,
+            command: args.command,
+            ..conf
+        })
     }
 
     pub fn check_lang_and_get(&self, job_lang: &str) -> Result<&Language, err::Error> {
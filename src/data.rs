@@ -0,0 +1,412 @@
+use crate::{
+    auth::Setter,
+    conf::{Case, Conf, Misc, Problem},
+    err,
+    err::raise_err,
+    problem::check_problem,
+    state::AppState,
+    utils::{self, natural_cmp},
+};
+use actix_web::{delete, get, post, put, web, Responder, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+// Largest archive accepted by POST /problems/{id}/data
+pub const MAX_UPLOAD: usize = 256 << 20;
+
+#[derive(Serialize)]
+struct CaseData {
+    id: i32,
+    input_file: String,
+    answer_file: String,
+    // None when the file can't be read
+    input_sha256: Option<String>,
+    answer_sha256: Option<String>,
+    input_size: Option<u64>,
+    answer_size: Option<u64>,
+}
+
+fn checksum(content: &[u8]) -> String {
+    utils::hex(&Sha256::digest(content))
+}
+
+fn describe(prob: &Problem) -> Vec<CaseData> {
+    let read = |file: &str| fs::read(file).ok();
+    prob.cases
+        .iter()
+        .zip(1..)
+        .map(|(case, id)| {
+            let (input, answer) = (read(&case.input_file), read(&case.answer_file));
+            CaseData {
+                id,
+                input_file: case.input_file.clone(),
+                answer_file: case.answer_file.clone(),
+                input_sha256: input.as_deref().map(checksum),
+                answer_sha256: answer.as_deref().map(checksum),
+                input_size: input.map(|data| data.len() as u64),
+                answer_size: answer.map(|data| data.len() as u64),
+            }
+        })
+        .collect()
+}
+
+pub fn problem_dir(conf: &Conf, id: i32) -> PathBuf {
+    Path::new(&conf.data_dir).join(id.to_string())
+}
+
+// Files are named by their checksum, so replacing or reordering cases never
+// overwrites data a queued job may still be pointing at
+pub fn store(dir: &Path, content: &[u8]) -> io::Result<String> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(checksum(content));
+    if !path.exists() {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &path)?;
+    }
+    Ok(path.to_string_lossy().into_owned())
+}
+
+#[derive(Default)]
+pub struct Garbage {
+    // Problems whose replaced files are still on disk
+    pending: BTreeSet<i32>,
+    // Judgings outside the queue (dry runs, PUT /jobs/{id}) reading each problem
+    readers: BTreeMap<i32, usize>,
+}
+
+// Keeps the data files of a problem while a judging outside the queue reads them
+pub(crate) struct Reading<'a> {
+    state: &'a AppState,
+    id: i32,
+}
+
+pub(crate) fn reading(state: &AppState, id: i32) -> Reading<'_> {
+    *state.garbage.lock().unwrap().readers.entry(id).or_default() += 1;
+    Reading { state, id }
+}
+
+impl Drop for Reading<'_> {
+    fn drop(&mut self) {
+        let mut garbage = self.state.garbage.lock().unwrap();
+        if let Some(count) = garbage.readers.get_mut(&self.id) {
+            *count -= 1;
+            if *count == 0 {
+                garbage.readers.remove(&self.id);
+            }
+        }
+        drop(garbage);
+        collect_pending(self.state, self.id);
+    }
+}
+
+// Files of the problem were replaced: drop the old ones once nothing uses them
+pub(crate) fn collect_garbage(state: &AppState, id: i32) {
+    state.garbage.lock().unwrap().pending.insert(id);
+    collect_pending(state, id);
+}
+
+// Drop stored files neither the cases nor the checker of the problem refer to
+// any more. Directories, like the statement, are left alone. Jobs handed to a
+// worker and the readers above may hold the old paths, so nothing is dropped
+// until the last of them is done; queued jobs pick up the new paths.
+pub(crate) fn collect_pending(state: &AppState, id: i32) {
+    let mut garbage = state.garbage.lock().unwrap();
+    if !garbage.pending.contains(&id) || garbage.readers.contains_key(&id) {
+        return;
+    }
+    let running = state.queue.running();
+    let prob = state.read(|db| {
+        let busy = running.iter().any(|&job_id| {
+            db.job(job_id)
+                .is_some_and(|job| job.submission.problem_id == id)
+        });
+        match busy {
+            true => None,
+            false => db.problem(id).cloned(),
+        }
+    });
+    let Some(prob) = prob else {
+        return;
+    };
+    garbage.pending.remove(&id);
+    drop(garbage);
+    let used: BTreeSet<PathBuf> = prob
+        .cases
+        .iter()
+        .flat_map(|case| [&case.input_file, &case.answer_file])
+        .chain(prob.misc.special_judge.iter().flatten())
+        .map(PathBuf::from)
+        .collect();
+    let Ok(entries) = fs::read_dir(problem_dir(&state.conf, id)) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if !path.is_dir() && !used.contains(&path) {
+            if let Err(err) = fs::remove_file(&path) {
+                log::warn!("{:?}: {}", path, err);
+            }
+        }
+    }
+}
+
+// Case ids moved, so must the ids kept in misc. None drops the case.
+fn remap(misc: &mut Misc, new_id: impl Fn(i32) -> Option<i32>) {
+    let remap_list = |ids: &mut Vec<i32>| *ids = ids.iter().filter_map(|&id| new_id(id)).collect();
+    if let Some(packing) = &mut misc.packing {
+        packing.iter_mut().for_each(remap_list);
+        packing.retain(|group| !group.is_empty());
+    }
+    misc.pretests.iter_mut().for_each(remap_list);
+    misc.samples.iter_mut().for_each(remap_list);
+}
+
+// Limits and score of a new case follow the case it replaces, or the last one
+fn new_case(prob: &Problem, index: usize, input_file: String, answer_file: String) -> Case {
+    let template = prob.cases.get(index).or(prob.cases.last());
+    Case {
+        score: template.map_or(0.0, |case| case.score),
+        input_file,
+        answer_file,
+        time_limit: template.map_or(1000000, |case| case.time_limit),
+        memory_limit: template.map_or(0, |case| case.memory_limit),
+    }
+}
+
+pub(crate) fn internal(err: io::Error) -> actix_web::Error {
+    err::Error::new(err::ErrorKind::ErrInternal, err.to_string()).into()
+}
+
+fn update(
+    state: &AppState,
+    id: i32,
+    f: impl FnOnce(&mut Problem) -> Result<()>,
+) -> Result<web::Json<Vec<CaseData>>> {
+    let prob = state.try_write(|db| {
+        let mut prob = db.check_prob_and_get(id)?.clone();
+        f(&mut prob)?;
+        check_problem(&state.conf, &prob)?;
+        db.put_problem(prob.clone());
+        Ok(prob)
+    })?;
+    collect_garbage(state, id);
+    Ok(web::Json(describe(&prob)))
+}
+
+pub struct DataPair {
+    pub name: String,
+    pub input: Vec<u8>,
+    pub answer: Vec<u8>,
+}
+
+// Pairs "<name>.in" with "<name>.ans" in natural order of the names
+pub fn pair_files(files: BTreeMap<String, Vec<u8>>) -> Result<Vec<DataPair>> {
+    let mut inputs = BTreeMap::new();
+    let mut answers = BTreeMap::new();
+    for (name, content) in files {
+        match name.rsplit_once('.') {
+            Some((stem, "in")) => inputs.insert(stem.to_string(), content),
+            Some((stem, "ans")) => answers.insert(stem.to_string(), content),
+            _ => {
+                log::info!("{}: not a data file, ignored", name);
+                continue;
+            }
+        };
+    }
+    if let Some(stem) = answers.keys().find(|stem| !inputs.contains_key(*stem)) {
+        raise_err!(
+            err::ErrorKind::ErrInvalidArgument,
+            "{}.ans has no .in",
+            stem
+        )
+    }
+    let mut res = vec![];
+    for (name, input) in inputs {
+        match answers.remove(&name) {
+            Some(answer) => res.push(DataPair {
+                name,
+                input,
+                answer,
+            }),
+            None => raise_err!(
+                err::ErrorKind::ErrInvalidArgument,
+                "{}.in has no .ans",
+                name
+            ),
+        }
+    }
+    res.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+    Ok(res)
+}
+
+fn untar(body: &[u8]) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    for entry in tar::Archive::new(body).entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?;
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let name = name.to_string();
+        let mut content = vec![];
+        entry.read_to_end(&mut content)?;
+        files.insert(name, content);
+    }
+    Ok(files)
+}
+
+#[get("/problems/{id}/data")]
+pub async fn get_data(
+    _: Setter,
+    id: web::Path<i32>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let prob = state.read(|db| db.check_prob_and_get(*id).cloned())?;
+    Ok(web::Json(describe(&prob)))
+}
+
+#[derive(Deserialize)]
+pub struct UploadQuery {
+    // Add the cases after the current ones instead of replacing them all
+    #[serde(default)]
+    append: bool,
+}
+
+// Takes a tar archive of *.in / *.ans pairs
+#[post("/problems/{id}/data")]
+pub async fn post_data(
+    _: Setter,
+    id: web::Path<i32>,
+    query: web::Query<UploadQuery>,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    state.read(|db| db.check_prob_and_get(id).map(|_| ()))?;
+    let files = match untar(&body) {
+        Ok(files) => files,
+        Err(err) => raise_err!(err::ErrorKind::ErrInvalidArgument, "Bad archive: {}", err),
+    };
+    let pairs = pair_files(files)?;
+    if pairs.is_empty() {
+        raise_err!(
+            err::ErrorKind::ErrInvalidArgument,
+            "No data in the archive."
+        )
+    }
+    let dir = problem_dir(&state.conf, id);
+    let mut stored = vec![];
+    for pair in pairs {
+        stored.push((
+            store(&dir, &pair.input).map_err(internal)?,
+            store(&dir, &pair.answer).map_err(internal)?,
+        ));
+    }
+    update(&state, id, |prob| {
+        let start = match query.append {
+            true => prob.cases.len(),
+            false => 0,
+        };
+        let cases: Vec<Case> = stored
+            .into_iter()
+            .enumerate()
+            .map(|(i, (input, answer))| new_case(prob, start + i, input, answer))
+            .collect();
+        if !query.append {
+            let len = cases.len() as i32;
+            remap(&mut prob.misc, |id| (id <= len).then_some(id));
+            prob.cases.clear();
+        }
+        prob.cases.extend(cases);
+        Ok(())
+    })
+}
+
+#[derive(Deserialize)]
+pub struct CaseBody {
+    input: String,
+    answer: String,
+}
+
+#[put("/problems/{id}/data/{case}")]
+pub async fn put_case(
+    _: Setter,
+    path: web::Path<(i32, i32)>,
+    body: web::Json<CaseBody>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let (id, case_id) = path.into_inner();
+    let dir = problem_dir(&state.conf, id);
+    let input = store(&dir, body.input.as_bytes()).map_err(internal)?;
+    let answer = store(&dir, body.answer.as_bytes()).map_err(internal)?;
+    update(&state, id, |prob| {
+        let Some(case) = usize::try_from(case_id - 1)
+            .ok()
+            .and_then(|i| prob.cases.get_mut(i))
+        else {
+            raise_err!(err::ErrorKind::ErrNotFound, "Case {} not found.", case_id)
+        };
+        (case.input_file, case.answer_file) = (input, answer);
+        Ok(())
+    })
+}
+
+#[delete("/problems/{id}/data/{case}")]
+pub async fn delete_case(
+    _: Setter,
+    path: web::Path<(i32, i32)>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let (id, case_id) = path.into_inner();
+    update(&state, id, |prob| {
+        if case_id < 1 || case_id as usize > prob.cases.len() {
+            raise_err!(err::ErrorKind::ErrNotFound, "Case {} not found.", case_id)
+        }
+        prob.cases.remove(case_id as usize - 1);
+        remap(&mut prob.misc, |id| match id.cmp(&case_id) {
+            std::cmp::Ordering::Less => Some(id),
+            std::cmp::Ordering::Equal => None,
+            std::cmp::Ordering::Greater => Some(id - 1),
+        });
+        Ok(())
+    })
+}
+
+// Body lists every case id once, in the new order
+#[post("/problems/{id}/data/order")]
+pub async fn post_order(
+    _: Setter,
+    id: web::Path<i32>,
+    order: web::Json<Vec<i32>>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let order = order.into_inner();
+    update(&state, id.into_inner(), |prob| {
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+        if sorted != (1..=prob.cases.len() as i32).collect::<Vec<_>>() {
+            raise_err!(
+                err::ErrorKind::ErrInvalidArgument,
+                "Order must list every case once."
+            )
+        }
+        prob.cases = order
+            .iter()
+            .map(|&id| prob.cases[id as usize - 1].clone())
+            .collect();
+        remap(&mut prob.misc, |id| {
+            order
+                .iter()
+                .position(|&old| old == id)
+                .map(|i| i as i32 + 1)
+        });
+        Ok(())
+    })
+}
@@ -1,55 +1,55 @@
 use crate::{
+    auth::{Admin, Credential, Role, Viewer},
     conf::{Conf, Problem},
-    err,
+    data, err,
     err::raise_err,
-    judge::{judge, CaseRes, CaseResult, PostJob, State},
-    utils::apmax,
+    judge::{judge_safe, CaseRes, CaseResult, PostJob, State},
+    ranklist::ScoringMode,
+    rejudge,
+    state::{AppState, Db},
+    storage::Record,
+    utils::rfc3339,
 };
-use actix_web::{get, post, put, web, Responder, Result};
-use lazy_static::lazy_static;
+use actix_web::{get, post, put, web, HttpResponse, Responder, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashMap};
-use std::sync::{Arc, Mutex};
-// use chrono::DateTime;
-
-lazy_static! {
-    // job, user: indexed
-    // problem_id: arbitrary
-    // MUST ACQUIRE JOB_SET BEFORE USER_VEC BEFORE CONTESTS
-    static ref JOB_SET: Arc<Mutex<BTreeSet<PostJobRes>>> = Arc::new(Mutex::new(BTreeSet::new()));
-    static ref USER_VEC: Arc<Mutex<Vec<User>>> = Arc::new(Mutex::new(Vec::new()));
-    static ref CONTESTS: Arc<Mutex<Vec<Contest>>> = Arc::new(Mutex::new(Vec::new()));
-}
+use std::borrow::Cow;
 
 // Judge related
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct PostJobRes {
-    id: i32,
-    created_time: String, //chrono::DateTime<chrono::Utc>
-    updated_time: String,
-    submission: PostJob,
-    state: State,
-    result: CaseResult,
-    score: f64,
-    cases: Vec<CaseRes>,
+    pub(crate) id: i32,
+    #[serde(with = "rfc3339")]
+    pub(crate) created_time: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    updated_time: DateTime<Utc>,
+    pub(crate) submission: PostJob,
+    pub(crate) state: State,
+    pub(crate) result: CaseResult,
+    pub(crate) score: f64,
+    pub(crate) cases: Vec<CaseRes>,
+    // Judged on pretests only, until the system test
+    #[serde(default)]
+    pub(crate) pretest: bool,
 }
 
 impl PostJobRes {
-    pub fn new(job: PostJob) -> Self {
-        let time = chrono::Utc::now().to_string();
+    fn new(id: i32, job: PostJob) -> Self {
+        let time = Utc::now();
         Self {
-            id: JOB_SET.lock().unwrap().len() as i32,
-            created_time: time.clone(),
+            id,
+            created_time: time,
             updated_time: time,
             submission: job,
             state: State::Queueing,
             result: CaseResult::Waiting,
             score: 0f64,
             cases: vec![],
+            pretest: false,
         }
     }
-    pub fn new_with_cases(job: PostJob, prob: &Problem) -> Self {
-        let mut res = Self::new(job);
+    fn new_with_cases(id: i32, job: PostJob, prob: &Problem) -> Self {
+        let mut res = Self::new(id, job);
         res.cases = (0..=prob.cases.len())
             .map(|id| CaseRes {
                 id: id as i32,
@@ -63,95 +63,159 @@ impl PostJobRes {
     pub fn merge(mut self, cases: Vec<CaseRes>, prob: &Problem) -> Self {
         let mut result = CaseResult::Accepted;
         let mut score = 0f64;
-        for (case_res, case_cfg) in cases.iter().skip(1).zip(prob.cases.iter()) {
-            if (result as i32) < (case_res.result as i32) {
-                result = case_res.result.clone();
+        for case_res in cases.iter().skip(1) {
+            if case_res.result != CaseResult::Skipped && (result as i32) < (case_res.result as i32)
+            {
+                result = case_res.result;
             }
-            if case_res.result == CaseResult::Accepted {
-                score += case_cfg.score;
+        }
+        let groups = match &prob.misc.packing {
+            Some(packing) => packing.clone(),
+            None => (1..=prob.cases.len() as i32).map(|id| vec![id]).collect(),
+        };
+        for group in groups.iter() {
+            let passed = group.iter().all(
+                |&id| matches!(cases.get(id as usize), Some(c) if c.result == CaseResult::Accepted),
+            );
+            if passed {
+                score += group
+                    .iter()
+                    .map(|&id| prob.cases[id as usize - 1].score)
+                    .sum::<f64>();
             }
         }
         self.state = State::Finished;
         log::info!("cases[0].result: {:?}", cases[0].result);
-        if cases[0].result == CaseResult::CompilationError {
-            result = CaseResult::CompilationError;
+        if let CaseResult::CompilationError | CaseResult::SystemError = cases[0].result {
+            result = cases[0].result;
         }
         (self.result, self.score, self.cases) = (result, score, cases);
         self
     }
-}
 
-impl std::cmp::PartialEq for PostJobRes {
-    fn eq(&self, other: &Self) -> bool {
-        // TODO: is this enough?
-        // self.created_time == other.created_time
-        self.id == other.id
+    // All a contestant may see while an OI contest runs: the compilation verdict
+    fn redacted(&self) -> Self {
+        let mut job = self.clone();
+        job.cases.truncate(1);
+        job.score = 0f64;
+        if job.state == State::Finished {
+            job.result = job
+                .cases
+                .first()
+                .map_or(CaseResult::Waiting, |case| case.result);
+        }
+        job
     }
-}
-impl std::cmp::Eq for PostJobRes {}
-impl std::cmp::PartialOrd for PostJobRes {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        // TODO check whether bigger one becomes first
-        // Some(self.created_time.cmp(&other.created_time))
-        Some(self.id.cmp(&other.id))
+
+    // Frozen jobs show nothing past being submitted
+    fn frozen(&self) -> Self {
+        let mut job = self.redacted();
+        job.cases.clear();
+        job.result = CaseResult::Waiting;
+        job
+    }
+
+    pub fn fail(mut self, reason: &str) -> Self {
+        self.state = State::Finished;
+        (self.result, self.score) = (CaseResult::SystemError, 0f64);
+        if let Some(case) = self.cases.get_mut(0) {
+            case.result = CaseResult::SystemError;
+            case.info = reason.to_string();
+        }
+        self
     }
 }
-impl std::cmp::Ord for PostJobRes {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+
+impl Record for PostJobRes {
+    fn id(&self) -> i32 {
+        self.id
     }
 }
 
 // Query related
 #[derive(Serialize, Deserialize)]
-struct JobQuery {
+pub struct JobQuery {
     user_id: Option<i32>,
     user_name: Option<String>,
     contest_id: Option<i32>,
     problem_id: Option<i32>,
     language: Option<String>,
-    // DateTime<chrono::Utc>
-    from: Option<String>,
-    to: Option<String>,
+    #[serde(default, with = "rfc3339::option")]
+    from: Option<DateTime<Utc>>,
+    #[serde(default, with = "rfc3339::option")]
+    to: Option<DateTime<Utc>>,
     state: Option<State>,
     result: Option<CaseResult>,
 }
 
-pub async fn upd_job(job_res: PostJobRes) -> Result<impl Responder> {
-    let mut set = JOB_SET.lock().unwrap();
-    set.replace(job_res);
-    Ok(actix_web::HttpResponse::Ok())
+pub fn add_job(db: &mut Db, job: PostJob, prob: &Problem) -> PostJobRes {
+    let pretests = db.contest(job.contest_id).is_some_and(|c| c.pretests);
+    let mut job_res = PostJobRes::new_with_cases(db.jobs.len() as i32, job, prob);
+    job_res.pretest = pretests && prob.misc.pretests.is_some();
+    db.put_job(job_res.clone());
+    job_res
+}
+
+pub fn unfinished_jobs(db: &Db) -> Vec<i32> {
+    db.jobs
+        .values()
+        .filter(|job| matches!(job.state, State::Queueing | State::Running))
+        .map(|job| job.id)
+        .collect()
+}
+
+pub fn set_job_state(db: &mut Db, id: i32, state: State) -> Option<PostJobRes> {
+    let mut job_res = db.job(id)?.clone();
+    job_res.state = state;
+    job_res.updated_time = Utc::now();
+    db.put_job(job_res.clone());
+    Some(job_res)
+}
+
+pub(crate) fn visible<'a>(db: &Db, job: &'a PostJobRes, viewer: &Viewer) -> Cow<'a, PostJobRes> {
+    let mut job = Cow::Borrowed(job);
+    if let Some(contest) = db.contest(job.submission.contest_id) {
+        if !viewer.admin() && contest.sealed() {
+            job = Cow::Owned(job.redacted());
+        }
+        // Others' verdicts after the freeze stay hidden as on the ranklist
+        if !viewer.owns(job.submission.user_id) && contest.hides(&job) {
+            job = Cow::Owned(job.frozen());
+        }
+    }
+    if !viewer.owns(job.submission.user_id) {
+        job.to_mut().submission.source_code.clear();
+    }
+    job
 }
 
 #[get("/jobs/{job_id}")]
-async fn get_job(job_id: web::Path<i32>) -> Result<impl Responder> {
-    let set = JOB_SET.lock().unwrap();
-    let ls: Vec<_> = set.iter().filter(|x| x.id == job_id.clone()).collect();
-    assert!(ls.len() <= 1);
-    if let Some(&job) = ls.get(0) {
-        Ok(web::Json(job.clone()))
-    } else {
-        Err(err::Error::new(
-            err::ErrorKind::ErrNotFound,
-            format!("Job {} not found.", job_id),
-        )
-        .into())
+pub async fn get_job(
+    job_id: web::Path<i32>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let job = state.read(|db| {
+        db.job(*job_id)
+            .map(|job| visible(db, job, &viewer).into_owned())
+    });
+    match job {
+        Some(job) => Ok(web::Json(job)),
+        None => raise_err!(err::ErrorKind::ErrNotFound, "Job {} not found.", job_id),
     }
 }
 
 #[put("/jobs/{job_id}")] // Rejudge after done?
-async fn put_job(job_id: web::Path<i32>, conf: web::Data<Conf>) -> Result<impl Responder> {
-    let set = JOB_SET.lock().unwrap();
-    let tmp_res = PostJobRes {
-        id: job_id.clone(),
-        ..Default::default()
-    };
-    let mut job_res = if let Some(job_res) = set.get(&tmp_res) {
-        job_res.clone()
-    } else {
-        raise_err!(err::ErrorKind::ErrNotFound, "Job {} not found.", job_id)
+pub async fn put_job(
+    _: Admin,
+    job_id: web::Path<i32>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let conf = &state.conf;
+    let mut job_res = match state.read(|db| db.job(*job_id).cloned()) {
+        Some(job_res) => job_res,
+        None => raise_err!(err::ErrorKind::ErrNotFound, "Job {} not found.", job_id),
     };
-    drop(set);
     if job_res.state != State::Finished {
         raise_err!(
             err::ErrorKind::ErrInvalidState,
@@ -159,19 +223,24 @@ async fn put_job(job_id: web::Path<i32>, conf: web::Data<Conf>) -> Result<impl R
             job_id
         )
     }
-    job_res.updated_time = chrono::Utc::now().to_string();
-    let case_res = judge(&job_res.submission, &conf)?;
-    let prob = conf.check_prob_and_get(job_res.submission.problem_id)?;
-    let job_res = job_res.merge(case_res, prob);
-    upd_job(job_res.clone()).await?;
+    job_res.updated_time = Utc::now();
+    let lang = conf.check_lang_and_get(&job_res.submission.language)?;
+    let _reading = data::reading(&state, job_res.submission.problem_id);
+    let prob = state.read(|db| {
+        db.check_prob_and_get(job_res.submission.problem_id)
+            .cloned()
+    })?;
+    let case_res = judge_safe(&job_res.submission, lang, &prob, job_res.pretest);
+    let old_res = job_res.clone();
+    let job_res = job_res.merge(case_res, &prob);
+    let saved = state.write(|db| db.put_job(job_res.clone()));
+    rejudge::record(&state, &old_res, &job_res);
+    saved?;
     Ok(web::Json(job_res))
 }
 
-#[get("/jobs")]
-async fn get_jobs(info: web::Query<JobQuery>) -> Result<impl Responder> {
-    let job_set = JOB_SET.lock().unwrap();
-    let user_vec = USER_VEC.lock().unwrap();
-
+// Filters see what the viewer sees, so redacted verdicts can't be probed
+pub fn filter_jobs<'a>(db: &'a Db, info: &JobQuery, viewer: &Viewer) -> Vec<Cow<'a, PostJobRes>> {
     macro_rules! check_job {
         ($job: tt, $info: ident, $elm: ident) => {
             if let Some(elm) = &$info.$elm {
@@ -181,13 +250,14 @@ async fn get_jobs(info: web::Query<JobQuery>) -> Result<impl Responder> {
             }
         };
     }
-    let vec: Vec<PostJobRes> = job_set
-        .iter()
+    db.jobs
+        .values()
+        .map(|job| visible(db, job, viewer))
         .filter(|job| {
             if let Some(user_name) = &info.user_name {
-                let vec_user = user_vec.get(job.submission.user_id as usize).unwrap();
-                if &vec_user.name != user_name {
-                    return false;
+                match db.user(job.submission.user_id) {
+                    Some(user) if &user.name == user_name => {}
+                    _ => return false,
                 }
             }
             check_job!((job.submission), info, user_id);
@@ -196,87 +266,237 @@ async fn get_jobs(info: web::Query<JobQuery>) -> Result<impl Responder> {
             check_job!((job.submission), info, language);
             check_job!(job, info, state);
             check_job!(job, info, result);
-            if let Some(from) = &info.from {
-                if from.cmp(&job.created_time) == std::cmp::Ordering::Greater {
-                    return false;
-                }
+            if info.from.is_some_and(|from| from > job.created_time) {
+                return false;
             }
-            if let Some(to) = &info.to {
-                if to.cmp(&job.created_time) == std::cmp::Ordering::Less {
-                    return false;
-                }
+            if info.to.is_some_and(|to| to < job.created_time) {
+                return false;
             }
             true
         })
-        .cloned()
-        .collect();
-    Ok(web::Json(vec))
+        .collect()
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SortKey {
+    #[default]
+    Id,
+    CreatedTime,
+    Score,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+const MAX_PER_PAGE: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    // Pages start from 1, everything is returned if neither is given
+    page: Option<usize>,
+    per_page: Option<usize>,
+    #[serde(default)]
+    sort_by: SortKey,
+    #[serde(default)]
+    order: Order,
+    // Leave out source_code and cases
+    #[serde(default)]
+    brief: bool,
+}
+
+fn brief_job(job: &PostJobRes) -> serde_json::Value {
+    let mut value = serde_json::to_value(job).unwrap();
+    let obj = value.as_object_mut().unwrap();
+    obj.remove("cases");
+    if let Some(submission) = obj.get_mut("submission").and_then(|s| s.as_object_mut()) {
+        submission.remove("source_code");
+    }
+    value
+}
+
+#[get("/jobs")]
+pub async fn get_jobs(
+    info: web::Query<JobQuery>,
+    page: web::Query<PageQuery>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let per_page = match (page.page, page.per_page) {
+        (None, None) => None,
+        (_, Some(n)) if n == 0 || n > MAX_PER_PAGE => raise_err!(
+            err::ErrorKind::ErrInvalidArgument,
+            "per_page should be in 1..={}.",
+            MAX_PER_PAGE
+        ),
+        (Some(0), _) => raise_err!(err::ErrorKind::ErrInvalidArgument, "Pages start from 1."),
+        (_, n) => Some(n.unwrap_or(20)),
+    };
+    let (total, jobs) = state.read(|db| {
+        let mut jobs = filter_jobs(db, &info, &viewer);
+        // Stable sort, ties stay ordered by id
+        match page.sort_by {
+            SortKey::Id => {}
+            SortKey::CreatedTime => jobs.sort_by_key(|job| job.created_time),
+            SortKey::Score => jobs.sort_by(|a, b| a.score.total_cmp(&b.score)),
+        }
+        if let Order::Desc = page.order {
+            jobs.reverse();
+        }
+        let total = jobs.len();
+        let (skip, take) = match per_page {
+            Some(n) => ((page.page.unwrap_or(1) - 1) * n, n),
+            None => (0, total),
+        };
+        let jobs: Vec<serde_json::Value> = jobs
+            .into_iter()
+            .skip(skip)
+            .take(take)
+            .map(|job| match page.brief {
+                true => brief_job(&job),
+                false => serde_json::to_value(&job).unwrap(),
+            })
+            .collect();
+        (total, jobs)
+    });
+    let mut res = HttpResponse::Ok();
+    res.insert_header(("X-Total-Count", total.to_string()));
+    if let Some(n) = per_page {
+        res.insert_header(("X-Total-Pages", total.div_ceil(n).to_string()));
+    }
+    Ok(res.json(jobs))
 }
 
 // User related
-const fn nul_id() -> i32 {
+pub(crate) const fn nul_id() -> i32 {
     -1
 }
 #[derive(Clone, Default, Deserialize, Serialize)]
 pub struct User {
     #[serde(default = "nul_id")]
-    id: i32,
-    name: String,
+    pub(crate) id: i32,
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) role: Role,
 }
 
-pub fn init_user() {
-    let mut users = USER_VEC.lock().unwrap();
-    let mut contests = CONTESTS.lock().unwrap();
-    contests[0].user_ids.push(0);
-    users.push(User {
+impl Record for User {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+pub fn init_user(db: &mut Db, conf: &Conf) {
+    if !db.users.is_empty() {
+        return;
+    }
+    let admin = &conf.admin;
+    db.put_user(User {
         id: 0,
-        name: "root".to_string(),
+        name: admin.name.clone(),
+        role: Role::Admin,
     });
+    if let Some(password) = &admin.password {
+        db.put_credential(Credential::new(0, password));
+    }
+    let mut contest = db.contests[0].clone();
+    contest.user_ids.push(0);
+    db.put_contest(contest);
 }
 
-pub fn check_user(id: i32) -> Result<()> {
-    let users = USER_VEC.lock().unwrap();
-    if users.len() > id as usize {
-        Ok(())
-    } else {
-        raise_err!(err::ErrorKind::ErrNotFound, "")
+pub fn check_user(db: &Db, id: i32) -> Result<()> {
+    match db.user(id) {
+        Some(_) => Ok(()),
+        None => raise_err!(err::ErrorKind::ErrNotFound, ""),
     }
 }
 
+#[derive(Deserialize)]
+pub struct PostUser {
+    #[serde(default = "nul_id")]
+    id: i32,
+    name: String,
+    // Only admins may hand out roles, an edit without one keeps the old role
+    role: Option<Role>,
+    password: Option<String>,
+}
+
 #[post("/users")]
-async fn post_user(user: web::Json<User>) -> Result<impl Responder> {
-    let user = user.into_inner();
-    let mut users = USER_VEC.lock().unwrap();
-    let mut contests = CONTESTS.lock().unwrap();
-    let len = users.len();
-    if user.id == nul_id() {
-        if users.iter().any(|cur| cur.name == user.name) {
+pub async fn post_user(
+    body: web::Json<PostUser>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let PostUser {
+        id,
+        name,
+        role,
+        password,
+    } = body.into_inner();
+    let missing = match &password {
+        Some(password) => password.is_empty(),
+        None => state.conf.server.require_auth && id == nul_id(),
+    };
+    if missing {
+        raise_err!(err::ErrorKind::ErrInvalidArgument, "Password required.")
+    }
+    if role.is_some() && !viewer.admin() {
+        raise_err!(err::ErrorKind::ErrForbidden, "Only admins may set roles.")
+    }
+    if id != nul_id() && !viewer.owns(id) {
+        raise_err!(err::ErrorKind::ErrForbidden, "Cannot change user {}.", id)
+    }
+    // Hashing is slow, keep it out of the lock
+    let cred = password.map(|password| Credential::new(id, &password));
+    state.try_write(|db| {
+        // Names are what users log in with
+        if db.users.iter().any(|cur| cur.name == name && cur.id != id) {
             raise_err!(
                 err::ErrorKind::ErrInvalidArgument,
                 "User name '{}' already exists",
-                user.name
+                name
             )
         }
-        let new_user = User {
-            id: len as i32,
-            name: user.name,
-        };
-        users.push(new_user.clone());
-        contests[0].user_ids.push(users.len() as i32 - 1);
-        Ok(web::Json(new_user))
-    } else {
-        if let Some(elm) = users.get_mut(user.id as usize) {
-            *elm = user.clone();
-            Ok(web::Json(user))
+        let user = if id == nul_id() {
+            let new_user = User {
+                id: db.users.len() as i32,
+                name,
+                role: role.unwrap_or_default(),
+            };
+            db.put_user(new_user.clone());
+            let mut contest = db.contests[0].clone();
+            contest.user_ids.push(new_user.id);
+            db.put_contest(contest);
+            new_user
         } else {
-            raise_err!(err::ErrorKind::ErrNotFound, "User {} not found.", user.id)
+            let old_role = match db.user(id) {
+                Some(user) => user.role,
+                None => raise_err!(err::ErrorKind::ErrNotFound, "User {} not found.", id),
+            };
+            let user = User {
+                id,
+                name,
+                role: role.unwrap_or(old_role),
+            };
+            db.put_user(user.clone());
+            user
+        };
+        if let Some(mut cred) = cred {
+            cred.id = user.id;
+            db.put_credential(cred);
         }
-    }
+        Ok(web::Json(user))
+    })
 }
 
 #[get("/users")]
-async fn get_users() -> Result<impl Responder> {
-    let users: Vec<User> = USER_VEC.lock().unwrap().clone();
+pub async fn get_users(state: web::Data<AppState>) -> Result<impl Responder> {
+    let users: Vec<User> = state.read(|db| db.users.clone());
     Ok(web::Json(users))
 }
 
@@ -284,224 +504,393 @@ async fn get_users() -> Result<impl Responder> {
 #[derive(Clone, Deserialize, Serialize, Default)]
 pub struct Contest {
     #[serde(default = "nul_id")]
-    id: i32,
-    name: String,
-    from: String,
-    to: String,
-    problem_ids: Vec<i32>,
-    user_ids: Vec<i32>,
+    pub(crate) id: i32,
+    pub(crate) name: String,
+    #[serde(with = "rfc3339")]
+    pub(crate) from: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    pub(crate) to: DateTime<Utc>,
+    #[serde(default)]
+    pub(crate) scoring_mode: ScoringMode,
+    // Hide verdicts and scores from contestants until the contest ends
+    #[serde(default)]
+    pub(crate) oi_mode: bool,
+    // Judge pretests only until the end, then system test the final submissions
+    #[serde(default)]
+    pub(crate) pretests: bool,
+    // Jobs picked for the system test, set once it has started
+    #[serde(default)]
+    pub(crate) system_test: Option<Vec<i32>>,
+    // Submissions from this time on are hidden from the public ranklist
+    #[serde(default, with = "rfc3339::option")]
+    pub(crate) freeze_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub(crate) unfrozen: bool,
+    // (user, problem) cells already shown by the reveal
+    #[serde(default)]
+    pub(crate) revealed: Vec<(i32, i32)>,
+    #[serde(default)]
+    pub(crate) visibility: Visibility,
+    // Needed to register for an invite-only contest, never shown to contestants
+    #[serde(default)]
+    invite_code: Option<String>,
+    // Self-registration is open from registration_from (or always) until
+    // registration_to (or the end of the contest)
+    #[serde(default, with = "rfc3339::option")]
+    registration_from: Option<DateTime<Utc>>,
+    #[serde(default, with = "rfc3339::option")]
+    registration_to: Option<DateTime<Utc>>,
+    pub(crate) problem_ids: Vec<i32>,
+    pub(crate) user_ids: Vec<i32>,
+    // Team contests rank teams instead of users, the limit counts the whole team
+    #[serde(default)]
+    pub(crate) team_contest: bool,
+    #[serde(default)]
+    pub(crate) team_ids: Vec<i32>,
     submission_limit: i32,
 }
-#[derive(Clone, Default, Serialize)]
-pub struct UserRank {
-    user: User,
-    rank: i32,
-    #[serde(skip)]
-    sub_cnt: i32,
-    #[serde(skip)]
-    sub_time: String,
-    scores: Vec<f64>,
-}
-
-pub fn init_contest(conf: &Conf) {
-    let mut contests = CONTESTS.lock().unwrap();
-    let problem_ids: Vec<i32> = conf.problems.iter().map(|prob| prob.id).collect();
-    contests.push(Contest {
-        problem_ids: problem_ids,
-        from: chrono::Utc::now().to_string(),
-        to: String::from("99999999"),
-        submission_limit: 99999999,
-        ..Default::default()
-    });
+
+// Who may join a contest besides the users an admin puts in it
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    Public,
+    Invite,
+    #[default]
+    Private,
 }
 
-pub fn check_contest(job: &PostJob) -> Result<impl Responder> {
+impl Contest {
+    // Private contests only show up for their members, contest 0 is the global one
+    pub(crate) fn shown_to(&self, db: &Db, viewer: &Viewer) -> bool {
+        self.id == 0
+            || viewer.admin()
+            || self.visibility != Visibility::Private
+            || viewer
+                .user_id
+                .is_some_and(|id| db.entrant_of(self, id).is_some())
+    }
+    fn redacted(&self, viewer: &Viewer) -> Self {
+        let mut contest = self.clone();
+        if !viewer.admin() {
+            contest.invite_code = None;
+        }
+        contest
+    }
+    pub(crate) fn sealed(&self) -> bool {
+        self.oi_mode && Utc::now() < self.to
+    }
+    pub(crate) fn hides(&self, job: &PostJobRes) -> bool {
+        let sub = &job.submission;
+        !self.unfrozen
+            && self.freeze_time.is_some_and(|t| job.created_time >= t)
+            && !self.revealed.contains(&(sub.user_id, sub.problem_id))
+    }
+}
+
+impl Record for Contest {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+// Problems of the config are only added when missing, later edits live in the
+// storage. Editing a stored problem in the config does nothing, that gets a
+// warning; change it through /problems or start with a fresh storage.
+pub fn init_problems(db: &mut Db, conf: &Conf) {
+    for prob in conf.problems.iter() {
+        match db.problem(prob.id) {
+            None => db.put_problem(prob.clone()),
+            Some(stored)
+                if serde_json::to_value(stored).ok() != serde_json::to_value(prob).ok() =>
+            {
+                log::warn!(
+                    "problem {} in the config differs from the stored one, keeping the stored one",
+                    prob.id
+                )
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+pub fn init_contest(db: &mut Db) {
+    let problem_ids: Vec<i32> = db.problems.keys().copied().collect();
+    let contest = match db.contest(0) {
+        // Loaded from storage, problems may have been added since
+        Some(contest) => Contest {
+            problem_ids,
+            ..contest.clone()
+        },
+        None => Contest {
+            id: 0,
+            problem_ids,
+            // Kept within four-digit years so it survives a round trip
+            from: DateTime::<Utc>::default(),
+            to: rfc3339::parse("9999-12-31T23:59:59.999Z").unwrap(),
+            submission_limit: 99999999,
+            ..Default::default()
+        },
+    };
+    db.put_contest(contest);
+}
+
+pub fn check_contest(db: &Db, job: &PostJob) -> Result<()> {
     use err::ErrorKind::*;
-    let contests = CONTESTS.lock().unwrap();
-    let jobs = JOB_SET.lock().unwrap();
     // NOT_FOUND
-    let contest = match contests.get(job.contest_id as usize) {
+    let contest = match db.contest(job.contest_id) {
         Some(contest) => contest,
         None => raise_err!(ErrNotFound, ""),
     };
     // INVALID_ARGUMENT
-    if !contest.user_ids.iter().any(|id| job.user_id == *id) {
-        raise_err!(ErrInvalidArgument, "user {} not found", job.user_id);
-    }
-    if !contest.problem_ids.iter().any(|id| job.problem_id == *id) {
+    let entrant = match db.entrant_of(contest, job.user_id) {
+        Some(entrant) => entrant,
+        None => raise_err!(ErrInvalidArgument, "user {} not found", job.user_id),
+    };
+    if !contest.problem_ids.contains(&job.problem_id) {
         raise_err!(ErrInvalidArgument, "prob {} not found", job.problem_id);
     }
-    let time = chrono::Utc::now().to_string(); // Or use created_time?
+    let time = Utc::now(); // Or use created_time?
     if time < contest.from || time > contest.to {
         raise_err!(ErrInvalidArgument, "bad submission time");
     }
     // RATE_LIMIT
-    let cnt = jobs
-        .iter()
-        .filter(|hist| {
-            hist.submission.contest_id == contest.id && hist.submission.user_id == job.user_id
-        })
-        .count();
+    let cnt: usize = db
+        .members(contest, entrant)
+        .into_iter()
+        .map(|user_id| db.jobs_of(contest.id, user_id).count())
+        .sum();
     if cnt as i32 >= contest.submission_limit {
         raise_err!(ErrRateLimit, "")
     }
-    Ok("")
+    Ok(())
+}
+
+// Admins may put anyone in any contest, everybody else has to follow its visibility
+pub fn check_register(
+    db: &Db,
+    contest_id: i32,
+    viewer: &Viewer,
+    invite_code: Option<&str>,
+) -> Result<()> {
+    use err::ErrorKind::*;
+    // NOT_FOUND
+    let contest = match db.contest(contest_id) {
+        Some(contest) if contest.id != 0 && contest.shown_to(db, viewer) => contest,
+        _ => raise_err!(ErrNotFound, "Contest {} not found.", contest_id),
+    };
+    if viewer.admin() {
+        return Ok(());
+    }
+    // FORBIDDEN
+    match contest.visibility {
+        Visibility::Public => {}
+        Visibility::Invite if contest.invite_code.as_deref() == invite_code => {}
+        Visibility::Invite => raise_err!(ErrForbidden, "Wrong invite code."),
+        Visibility::Private => raise_err!(ErrForbidden, "Contest {} is private.", contest_id),
+    }
+    // INVALID_ARGUMENT
+    let time = Utc::now();
+    let from = contest
+        .registration_from
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
+    if time < from || time > contest.registration_to.unwrap_or(contest.to) {
+        raise_err!(ErrInvalidArgument, "Registration is closed.");
+    }
+    Ok(())
 }
 
 #[post("/contests")]
-async fn post_contest(
+pub async fn post_contest(
+    _: Admin,
     contest: web::Json<Contest>,
-    conf: web::Data<Conf>,
+    state: web::Data<AppState>,
 ) -> Result<impl Responder> {
-    let users = USER_VEC.lock().unwrap();
-    let mut contests = CONTESTS.lock().unwrap();
-    // Check contests
     let contest = contest.into_inner();
-    let invld_prob = contest
-        .problem_ids
-        .iter()
-        .any(|id| !conf.problems.iter().any(|prob| prob.id == *id));
-    let invld_user = contest.user_ids.iter().any(|id| users.len() as i32 <= *id);
-    log::info!("invld_prob: {}, invld_user: {}", invld_prob, invld_user);
-    if invld_prob || invld_user || contest.id == 0 {
-        // TODO check contest 0 behavior
-        raise_err!(err::ErrorKind::ErrNotFound, "");
-    }
-
-    if contest.id == nul_id() {
-        let len = contests.len();
-        let contest = Contest {
-            id: len as i32,
-            ..contest
-        };
-        contests.push(contest.clone());
-        Ok(web::Json(contest))
-    } else {
-        if let Some(entry) = contests.get_mut(contest.id as usize) {
-            *entry = contest.clone();
-            Ok(web::Json(contest))
-        } else {
+    state.try_write(|db| {
+        // Check contests
+        let invld_prob = contest
+            .problem_ids
+            .iter()
+            .any(|id| db.problem(*id).is_none());
+        let invld_user = contest.user_ids.iter().any(|&id| db.user(id).is_none());
+        let invld_team = contest.team_ids.iter().any(|&id| db.team(id).is_none());
+        log::info!("invld_prob: {}, invld_user: {}", invld_prob, invld_user);
+        if contest.from > contest.to {
+            raise_err!(
+                err::ErrorKind::ErrInvalidArgument,
+                "Contest ends before it starts."
+            );
+        }
+        if contest
+            .freeze_time
+            .is_some_and(|t| t < contest.from || t > contest.to)
+        {
+            raise_err!(
+                err::ErrorKind::ErrInvalidArgument,
+                "Freeze time out of the contest."
+            );
+        }
+        if contest
+            .registration_from
+            .zip(contest.registration_to)
+            .is_some_and(|(from, to)| from > to)
+        {
+            raise_err!(
+                err::ErrorKind::ErrInvalidArgument,
+                "Registration ends before it starts."
+            );
+        }
+        if contest.visibility == Visibility::Invite
+            && contest.invite_code.as_deref().is_none_or(str::is_empty)
+        {
+            raise_err!(
+                err::ErrorKind::ErrInvalidArgument,
+                "Invite-only contests need an invite code."
+            );
+        }
+        if !contest.team_contest && !contest.team_ids.is_empty() {
+            raise_err!(
+                err::ErrorKind::ErrInvalidArgument,
+                "Teams in a contest that is not a team contest."
+            );
+        }
+        let mut members: Vec<i32> = contest
+            .team_ids
+            .iter()
+            .filter_map(|&id| db.team(id))
+            .flat_map(|team| team.user_ids.iter().copied())
+            .collect();
+        let total = members.len();
+        members.sort_unstable();
+        members.dedup();
+        if members.len() != total {
+            raise_err!(
+                err::ErrorKind::ErrInvalidArgument,
+                "A user is in more than one team."
+            );
+        }
+        if invld_prob || invld_user || invld_team || contest.id == 0 {
+            // TODO check contest 0 behavior
             raise_err!(err::ErrorKind::ErrNotFound, "");
         }
-    }
+
+        // The system test, unfreeze and reveal are run by the server, never set by clients
+        let contest = if contest.id == nul_id() {
+            Contest {
+                id: db.contests.len() as i32,
+                system_test: None,
+                unfrozen: false,
+                revealed: vec![],
+                ..contest
+            }
+        } else if let Some(old) = db.contest(contest.id) {
+            Contest {
+                system_test: old.system_test.clone(),
+                unfrozen: old.unfrozen,
+                revealed: old.revealed.clone(),
+                ..contest
+            }
+        } else {
+            raise_err!(err::ErrorKind::ErrNotFound, "");
+        };
+        db.put_contest(contest.clone());
+        Ok(web::Json(contest))
+    })
 }
 
 #[get("/contests")]
-async fn get_contests() -> Result<impl Responder> {
-    let mut contests: Vec<_> = CONTESTS.lock().unwrap().clone();
-    contests.drain(0..1);
+pub async fn get_contests(viewer: Viewer, state: web::Data<AppState>) -> Result<impl Responder> {
+    let contests: Vec<_> = state.read(|db| {
+        db.contests[1..]
+            .iter()
+            .filter(|contest| contest.shown_to(db, &viewer))
+            .map(|contest| contest.redacted(&viewer))
+            .collect()
+    });
     Ok(web::Json(contests))
 }
 
 #[get("/contests/{id}")]
-async fn get_contest(id: web::Path<i32>) -> Result<impl Responder> {
-    let contests = CONTESTS.lock().unwrap();
+pub async fn get_contest(
+    id: web::Path<i32>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
     let id = id.into_inner();
-    match contests.get(id as usize) {
-        Some(contest) => Ok(web::Json(contest.clone())),
+    let contest = state.read(|db| {
+        db.contest(id)
+            .filter(|contest| contest.shown_to(db, &viewer))
+            .map(|contest| contest.redacted(&viewer))
+    });
+    match contest {
+        Some(contest) => Ok(web::Json(contest)),
         None => raise_err!(err::ErrorKind::ErrNotFound, "Contest {} not found.", id),
     }
 }
 
-// TODO: switch to O(nlogn) version?
-#[derive(Deserialize)]
-struct RankRule {
-    scoring_rule: Option<String>,
-    tie_breaker: Option<String>,
-}
-#[get("/contests/{contest_id}/ranklist")]
-async fn get_ranklist(
-    contest_id: web::Path<i32>,
-    rule: web::Query<RankRule>,
-) -> Result<impl Responder> {
-    let jobs = JOB_SET.lock().unwrap();
-    let users = USER_VEC.lock().unwrap();
-    let contests = CONTESTS.lock().unwrap();
+#[derive(Default, Deserialize)]
+pub struct Register {
+    // Admins register others, everybody else registers themselves
+    user_id: Option<i32>,
+    // Team contests take a team the caller is in instead
+    team_id: Option<i32>,
+    invite_code: Option<String>,
+}
 
-    let id = contest_id.into_inner();
-    let contest = match contests.get(id as usize) {
-        Some(contest) => contest,
-        None => raise_err!(err::ErrorKind::ErrNotFound, "Contest {} not found.", id),
-    };
-    let mut user_ids: Vec<i32> = match id {
-        0 => (0..users.len() as i32).collect(),
-        _ => contest.user_ids.clone(),
+#[post("/contests/{id}/register")]
+pub async fn register(
+    id: web::Path<i32>,
+    body: Option<web::Json<Register>>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    let body = body.map(|body| body.into_inner()).unwrap_or_default();
+    let user_id = match (viewer.user_id, body.user_id) {
+        (_, Some(user_id)) if viewer.admin() => Some(user_id),
+        (user_id, _) => user_id,
     };
-    user_ids.sort();
-    let mut res: Vec<UserRank> = user_ids
-        .iter()
-        .map(|&user_id| {
-            let mut score_map: HashMap<i32, f64> = HashMap::new();
-            let (mut sub_cnt, mut sub_time) = (0, String::new());
-            for job in jobs.iter() {
-                let sub = &job.submission;
-                if (sub.contest_id == id || id == 0) && sub.user_id == user_id {
-                    let mut updated = true;
-                    score_map
-                        .entry(sub.problem_id)
-                        .and_modify(|s| match rule.scoring_rule.as_deref() {
-                            Some("latest") | None => *s = job.score,
-                            Some("highest") => {
-                                if *s < job.score {
-                                    *s = job.score;
-                                } else {
-                                    updated = false;
-                                }
-                            }
-                            _ => unreachable!(),
-                        })
-                        .or_insert(job.score);
-                    sub_cnt += 1;
-                    if updated {
-                        apmax(&mut sub_time, job.created_time.clone());
-                    }
-                }
-            }
-            let scores: Vec<f64> = contest
-                .problem_ids
-                .iter()
-                .map(|&id| score_map.entry(id).or_default().clone())
-                .collect();
-            if &sub_time == "" {
-                sub_time = String::from("9");
+    if user_id.is_none() && !viewer.admin() {
+        raise_err!(err::ErrorKind::ErrUnauthorized, "Login required.")
+    }
+    state.try_write(|db| {
+        check_register(db, id, &viewer, body.invite_code.as_deref())?;
+        let mut contest = db.contest(id).unwrap().clone();
+        // Registering twice is a no-op
+        if contest.team_contest {
+            let team = match body.team_id.and_then(|team_id| db.team(team_id)) {
+                Some(team) => team,
+                None => raise_err!(err::ErrorKind::ErrNotFound, "Team not found."),
+            };
+            if !team.user_ids.iter().any(|&id| viewer.owns(id)) {
+                raise_err!(err::ErrorKind::ErrForbidden, "Not a member of the team.")
             }
-            UserRank {
-                user: users.get(user_id as usize).unwrap().clone(),
-                rank: 0,
-                scores,
-                sub_cnt,
-                sub_time,
+            let taken = team.user_ids.iter().any(|&user_id| {
+                db.entrant_of(&contest, user_id)
+                    .is_some_and(|other| other != team.id)
+            });
+            if taken {
+                raise_err!(
+                    err::ErrorKind::ErrInvalidArgument,
+                    "A member is already in another team."
+                )
             }
-        })
-        .collect();
-    let rank_cmp = |a: &UserRank, b: &UserRank, tie_breaker: &Option<String>| {
-        let res = (b.scores.iter().sum::<f64>())
-            .partial_cmp(&a.scores.iter().sum::<f64>())
-            .unwrap();
-        if std::cmp::Ordering::Equal == res {
-            match tie_breaker.as_deref() {
-                None => res,
-                Some("submission_time") => a.sub_time.cmp(&b.sub_time),
-                Some("submission_count") => a.sub_cnt.cmp(&b.sub_cnt),
-                Some("user_id") => a.user.id.cmp(&b.user.id),
-                Some(_) => unreachable!(),
+            if !contest.team_ids.contains(&team.id) {
+                contest.team_ids.push(team.id);
+                db.put_contest(contest.clone());
             }
         } else {
-            res
+            let user_id = match user_id {
+                Some(user_id) => user_id,
+                None => raise_err!(err::ErrorKind::ErrInvalidArgument, "user_id required."),
+            };
+            check_user(db, user_id)?;
+            if !contest.user_ids.contains(&user_id) {
+                contest.user_ids.push(user_id);
+                db.put_contest(contest.clone());
+            }
         }
-    };
-    res.sort_by(|a, b| rank_cmp(a, b, &rule.tie_breaker));
-
-    res[0].rank = 1;
-    log::info!("{}: {}", res[0].user.id, res[0].sub_time);
-    for i in 1..res.len() {
-        // take for granted that user_id is increasing
-        let diff = rank_cmp(&res[i], &res[i - 1], &rule.tie_breaker) != std::cmp::Ordering::Equal;
-        log::info!("{}: {}", res[i].user.id, res[i].sub_time);
-        res[i].rank = match diff {
-            true => i as i32 + 1,
-            false => res[i - 1].rank,
-        };
-    }
-    Ok(web::Json(res))
+        Ok(web::Json(contest.redacted(&viewer)))
+    })
 }
@@ -1,12 +1,13 @@
 use actix_web::{
     error,
     http::{header::ContentType, StatusCode},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
-use derive_more::{Display, Error};
+use derive_more::Display;
 use serde::Serialize;
 
 #[derive(Display, Debug, Serialize)]
+#[allow(dead_code, clippy::enum_variant_names)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ErrorKind {
     ErrInvalidArgument,
@@ -15,6 +16,8 @@ pub enum ErrorKind {
     ErrRateLimit,
     ErrExternal,
     ErrInternal,
+    ErrForbidden,
+    ErrUnauthorized,
 }
 
 impl ErrorKind {
@@ -26,6 +29,8 @@ impl ErrorKind {
             Self::ErrRateLimit => 4,
             Self::ErrExternal => 5,
             Self::ErrInternal => 6,
+            Self::ErrForbidden => 7,
+            Self::ErrUnauthorized => 8,
         }
     }
 }
@@ -47,8 +52,8 @@ impl Error {
     pub fn new(reason: ErrorKind, message: String) -> Self {
         Self {
             code: reason.get_code(),
-            reason: reason,
-            message: message,
+            reason,
+            message,
         }
     }
 }
@@ -59,6 +64,8 @@ impl error::ResponseError for Error {
             1 | 2 | 4 => StatusCode::BAD_REQUEST,
             3 => StatusCode::NOT_FOUND,
             5 | 6 => StatusCode::INTERNAL_SERVER_ERROR,
+            7 => StatusCode::FORBIDDEN,
+            8 => StatusCode::UNAUTHORIZED,
             _ => unreachable!(),
         }
     }
@@ -69,6 +76,15 @@ impl error::ResponseError for Error {
     }
 }
 
+// Malformed query strings get the same error body as everything else
+pub fn query_error(err: error::QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    Error::new(ErrorKind::ErrInvalidArgument, err.to_string()).into()
+}
+
+pub fn json_error(err: error::JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    Error::new(ErrorKind::ErrInvalidArgument, err.to_string()).into()
+}
+
 macro_rules! raise_err {
     ($err: expr, $($args:tt)*) => {
         return Err(err::Error::new($err, format!($($args)*)).into())
@@ -1,14 +1,24 @@
 use crate::{
-    conf::{Conf, Problem, ProblemType},
-    db::{check_contest, check_user, upd_job, PostJobRes},
+    auth::{Viewer, Worker},
+    conf::{Case, Language, Problem, ProblemType},
+    data,
+    db::{add_job, check_contest, check_user, set_job_state, unfinished_jobs, PostJobRes},
     err,
+    err::raise_err,
+    state::AppState,
+    system_test,
 };
-use actix_web::{post, web, Responder, Result};
+use actix_web::{post, web, HttpResponse, Responder, Result};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs,
+    fs, io, panic,
+    path::Path,
     process::{Command, Stdio},
-    time,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread, time,
 };
 use wait_timeout::ChildExt;
 
@@ -32,7 +42,7 @@ pub enum State {
     Unknown,
 }
 
-#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 #[allow(dead_code)]
 pub enum CaseResult {
     Accepted = 0,
@@ -59,7 +69,7 @@ pub enum CaseResult {
     UnInitialized,
 }
 
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct CaseRes {
     pub id: i32,
     pub result: CaseResult,
@@ -68,68 +78,180 @@ pub struct CaseRes {
     pub info: String,
 }
 
-// TODO: unwrap <=> closure
-fn run_cases(dir: tempdir::TempDir, prob: &Problem) -> Vec<CaseRes> {
-    let exe_path = dir.path().join("code");
-    log::info!("exe_path: {:?}", exe_path);
-    let mut res: Vec<CaseRes> = prob
-        .cases
+fn run_case(
+    exe_path: &Path,
+    out_path: &Path,
+    case: &Case,
+    prob: &Problem,
+    id: usize,
+) -> io::Result<CaseRes> {
+    let in_file = fs::File::open(&case.input_file)?;
+    let out_file = fs::File::create(out_path)?;
+    // Run and estimate time
+    let now = time::Instant::now();
+    let mut child = Command::new(exe_path)
+        .stdin(in_file)
+        .stdout(out_file)
+        .stderr(Stdio::null())
+        .spawn()?;
+    let duration = time::Duration::from_micros(case.time_limit as u64 + 5e5 as u64);
+    let ret_code = match child.wait_timeout(duration)? {
+        Some(status) => status.code(),
+        None => {
+            child.kill()?;
+            child.wait()?;
+            None
+        }
+    };
+    let time = now.elapsed().as_micros();
+    // Find out result
+    let (result, info) = match ret_code {
+        None => (CaseResult::TimeLimitExceeded, String::new()),
+        Some(0) => check_output(out_path, case, prob)?,
+        Some(_) => (CaseResult::RuntimeError, String::new()),
+    };
+    Ok(CaseRes {
+        id: id as i32,
+        result,
+        time: time as u64,
+        memory: 0,
+        info,
+    })
+}
+
+fn check_output(out_path: &Path, case: &Case, prob: &Problem) -> io::Result<(CaseResult, String)> {
+    let mut diff = Command::new("diff");
+    let diff = match prob.r#type {
+        ProblemType::Standard => diff.arg("-w"),
+        ProblemType::Strict => &mut diff,
+        ProblemType::Spj => return special_judge(out_path, case, prob),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("problem type {:?} not supported", prob.r#type),
+            ))
+        }
+    };
+    let status = diff
+        .arg(&case.answer_file)
+        .arg(out_path)
+        .stdout(Stdio::null())
+        .status()?;
+    match status.code() {
+        Some(0) => Ok((CaseResult::Accepted, String::new())),
+        Some(1) => Ok((CaseResult::WrongAnswer, String::new())),
+        _ => Err(io::Error::other(format!("diff failed: {}", status))),
+    }
+}
+
+// The checker prints the verdict on its first line and the info on the second
+fn special_judge(out_path: &Path, case: &Case, prob: &Problem) -> io::Result<(CaseResult, String)> {
+    let cmd: Vec<String> = prob
+        .misc
+        .special_judge
         .iter()
-        .enumerate()
-        .map(|(id, case)| {
-            let in_file = fs::File::open(&case.input_file).unwrap();
-            let out_path = dir.path().join("code.out");
-            let out_file = fs::File::create(&out_path).unwrap();
-            // Run and estimate time
-            let now = time::Instant::now();
-            let mut child = std::process::Command::new(exe_path.to_str().unwrap())
-                .stdin(in_file)
-                .stdout(out_file)
-                .stderr(Stdio::null())
-                .spawn()
-                .unwrap();
-            let duration = time::Duration::from_micros(case.time_limit as u64 + 5e5 as u64);
-            let ret_code = match child.wait_timeout(duration).unwrap() {
-                Some(status) => status.code(),
-                None => {
-                    child.kill().unwrap();
-                    None
-                }
-            };
-            // Find out result
-            let case_res = match ret_code {
-                None => CaseResult::TimeLimitExceeded,
-                Some(x) if x > 0 => CaseResult::RuntimeError,
-                Some(0) => {
-                    let status = match prob.r#type {
-                        ProblemType::Standard => Command::new("diff")
-                            .args(["-w", &case.answer_file, out_path.to_str().unwrap()])
-                            .status()
-                            .expect("diff error"),
-                        ProblemType::Strict => Command::new("diff")
-                            .args([&case.answer_file, out_path.to_str().unwrap()])
-                            .status()
-                            .expect("diff error"),
-                        _ => todo!(),
-                    };
-                    if status.code().unwrap() == 0 {
-                        CaseResult::Accepted
-                    } else {
-                        CaseResult::WrongAnswer
+        .flatten()
+        .map(|arg| match arg.as_str() {
+            "%OUTPUT%" => out_path.to_string_lossy().into_owned(),
+            "%ANSWER%" => case.answer_file.clone(),
+            _ => arg.clone(),
+        })
+        .collect();
+    let spj_error = |info: String| Ok((CaseResult::SPJError, info));
+    let Some((program, args)) = cmd.split_first() else {
+        return spj_error("no special judge command".to_string());
+    };
+    let output = match Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) => output,
+        Err(err) => return spj_error(err.to_string()),
+    };
+    if !output.status.success() {
+        return spj_error(format!("special judge exited with {}", output.status));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    let verdict = lines.next().unwrap_or_default().trim();
+    let info = lines.next().unwrap_or_default().trim().to_string();
+    match serde_json::from_value(serde_json::Value::from(verdict)) {
+        Ok(result) => Ok((result, info)),
+        Err(_) => spj_error(format!("unknown verdict '{}'", verdict)),
+    }
+}
+
+// Cases of a packing group are run in order, and skipped after the first failure.
+// With `only`, cases outside it are skipped as well.
+fn run_group(dir: &Path, group: &[usize], prob: &Problem, only: Option<&[i32]>) -> Vec<CaseRes> {
+    let exe_path = dir.join("code");
+    let mut failed = false;
+    group
+        .iter()
+        .map(|&id| {
+            if failed || only.is_some_and(|only| !only.contains(&(id as i32))) {
+                return CaseRes {
+                    id: id as i32,
+                    result: CaseResult::Skipped,
+                    ..Default::default()
+                };
+            }
+            let out_path = dir.join(format!("code.{}.out", id));
+            let res = match run_case(&exe_path, &out_path, &prob.cases[id - 1], prob, id) {
+                Ok(res) => res,
+                Err(err) => {
+                    log::error!("case {} of problem {}: {}", id, prob.id, err);
+                    CaseRes {
+                        id: id as i32,
+                        result: CaseResult::SystemError,
+                        info: err.to_string(),
+                        ..Default::default()
                     }
                 }
-                _ => unreachable!("ret_code"),
             };
-            let time = now.elapsed().as_micros();
-            CaseRes {
-                id: (id + 1) as i32,
-                result: case_res,
-                time: time as u64,
-                memory: 0,
-                info: String::new(),
-            }
+            failed = res.result != CaseResult::Accepted;
+            res
         })
-        .collect();
+        .collect()
+}
+
+fn run_cases(dir: tempdir::TempDir, prob: &Problem, only: Option<&[i32]>) -> Vec<CaseRes> {
+    log::info!("exe_path: {:?}", dir.path().join("code"));
+    let groups: Vec<Vec<usize>> = match &prob.misc.packing {
+        Some(packing) => packing
+            .iter()
+            .map(|group| group.iter().map(|&id| id as usize).collect())
+            .collect(),
+        None => (1..=prob.cases.len()).map(|id| vec![id]).collect(),
+    };
+    let workers = prob
+        .misc
+        .parallel_cases
+        .unwrap_or(1)
+        .clamp(1, groups.len().max(1));
+    let next = AtomicUsize::new(0);
+    let mut res: Vec<CaseRes> = thread::scope(|s| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut res = vec![];
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        match groups.get(idx) {
+                            Some(group) => res.extend(run_group(dir.path(), group, prob, only)),
+                            None => break res,
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    res.sort_by_key(|case_res| case_res.id);
     // add Compilation result
     res.insert(
         0usize,
@@ -141,9 +263,13 @@ fn run_cases(dir: tempdir::TempDir, prob: &Problem) -> Vec<CaseRes> {
     res
 }
 
-pub fn judge(job: &PostJob, conf: &Conf) -> Result<Vec<CaseRes>> {
-    let lang = conf.check_lang_and_get(&job.language)?;
-    let prob = conf.check_prob_and_get(job.problem_id)?;
+// With pretests_only, only the pretests of the problem are run
+pub fn judge(
+    job: &PostJob,
+    lang: &Language,
+    prob: &Problem,
+    pretests_only: bool,
+) -> io::Result<Vec<CaseRes>> {
     // Compile
     let dir = tempdir::TempDir::new("oj")?;
     let file_path = dir.path().join(&lang.file_name);
@@ -178,26 +304,249 @@ pub fn judge(job: &PostJob, conf: &Conf) -> Result<Vec<CaseRes>> {
         Ok(cases)
     } else {
         // Compilation Success
-        Ok(run_cases(dir, prob))
+        let only = match pretests_only {
+            true => prob.misc.pretests.as_deref(),
+            false => None,
+        };
+        Ok(run_cases(dir, prob, only))
+    }
+}
+
+// Results of a job whose judging failed for reasons unrelated to the submission
+pub fn system_error(prob: &Problem, reason: &str) -> Vec<CaseRes> {
+    let mut cases = vec![CaseRes {
+        result: CaseResult::SystemError,
+        info: reason.to_string(),
+        ..Default::default()
+    }];
+    for id in 1..=prob.cases.len() {
+        cases.push(CaseRes {
+            id: id as i32,
+            result: CaseResult::Waiting,
+            ..Default::default()
+        });
+    }
+    cases
+}
+
+// Like judge, but errors and panics end up as System Error instead
+pub fn judge_safe(
+    job: &PostJob,
+    lang: &Language,
+    prob: &Problem,
+    pretests_only: bool,
+) -> Vec<CaseRes> {
+    let reason = match panic::catch_unwind(|| judge(job, lang, prob, pretests_only)) {
+        Ok(Ok(cases)) => return cases,
+        Ok(Err(err)) => err.to_string(),
+        Err(payload) => match payload.downcast_ref::<&str>() {
+            Some(msg) => msg.to_string(),
+            None => payload
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_else(|| "judge panicked".to_string()),
+        },
+    };
+    log::error!(
+        "judging submission to problem {} failed: {}",
+        prob.id,
+        reason
+    );
+    system_error(prob, &reason)
+}
+
+// Everything a worker needs to judge a job without reading the server config.
+// Files are sent as paths, workers share the server's filesystem
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Task {
+    pub job_id: i32,
+    pub submission: PostJob,
+    pub language: Language,
+    pub problem: Problem,
+    #[serde(default)]
+    pub pretests_only: bool,
+}
+
+fn dispatch(state: &AppState, job_id: i32) -> Option<Task> {
+    let conf = &state.conf;
+    state.write_logged(|db| {
+        let job_res = db.job(job_id)?.clone();
+        let submission = job_res.submission.clone();
+        let (language, problem) = match (
+            conf.check_lang_and_get(&submission.language),
+            db.check_prob_and_get(submission.problem_id),
+        ) {
+            (Ok(lang), Ok(prob)) => (lang.clone(), prob.clone()),
+            _ => {
+                log::error!("job {}: language or problem no longer exists", job_id);
+                db.put_job(job_res.fail("language or problem no longer exists"));
+                return None;
+            }
+        };
+        set_job_state(db, job_id, State::Running)?;
+        Some(Task {
+            job_id,
+            submission,
+            language,
+            problem,
+            pretests_only: job_res.pretest,
+        })
+    })
+}
+
+fn complete(state: &AppState, job_id: i32, cases: Vec<CaseRes>) -> Option<PostJobRes> {
+    let job_res = state.write_logged(|db| {
+        let job_res = db.job(job_id)?.clone();
+        let job_res = match db
+            .check_prob_and_get(job_res.submission.problem_id)
+            .cloned()
+        {
+            Ok(prob) if cases.len() == prob.cases.len() + 1 => job_res.merge(cases, &prob),
+            _ => job_res.fail("results do not match the problem"),
+        };
+        db.put_job(job_res.clone());
+        Some(job_res)
+    })?;
+    // Data files replaced while the job ran can go now
+    data::collect_pending(state, job_res.submission.problem_id);
+    Some(job_res)
+}
+
+// Jobs left Queueing or Running by a previous run of the server
+pub fn requeue_unfinished(state: &AppState) {
+    let job_ids = state.write_logged(|db| {
+        let job_ids = unfinished_jobs(db);
+        for &job_id in job_ids.iter() {
+            set_job_state(db, job_id, State::Queueing);
+        }
+        job_ids
+    });
+    for job_id in job_ids {
+        log::info!("requeue job {}", job_id);
+        state.queue.push(job_id);
     }
 }
 
+// Start in-process workers and the watcher that requeues jobs of silent remote workers
+pub fn spawn_workers(state: Arc<AppState>) {
+    for i in 0..state.conf.judge.local_workers {
+        let state = state.clone();
+        thread::spawn(move || {
+            let name = format!("local-{}", i);
+            loop {
+                let job_id = state.queue.wait_pop(&name);
+                let task = match dispatch(&state, job_id) {
+                    Some(task) => task,
+                    None => continue,
+                };
+                let cases = judge_safe(
+                    &task.submission,
+                    &task.language,
+                    &task.problem,
+                    task.pretests_only,
+                );
+                if state.queue.finish(job_id, &name) {
+                    complete(&state, job_id, cases);
+                }
+            }
+        });
+    }
+    let timeout = time::Duration::from_secs(state.conf.judge.heartbeat_timeout);
+    thread::spawn(move || loop {
+        thread::sleep(time::Duration::from_secs(1));
+        let expired = state.queue.requeue_expired(timeout);
+        state.write_logged(|db| {
+            for job_id in expired {
+                set_job_state(db, job_id, State::Queueing);
+            }
+        });
+        system_test::start(&state);
+    });
+}
+
 #[post("/jobs")]
-async fn post_jobs(body: web::Json<PostJob>, conf: web::Data<Conf>) -> Result<impl Responder> {
-    use tokio::task;
-    let job = body.into_inner();
-    let conf = conf.into_inner();
-    check_contest(&job)?;
-    check_user(job.user_id)?;
+pub async fn post_jobs(
+    body: web::Json<PostJob>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let mut job = body.into_inner();
+    // Logged in users always submit as themselves, only admins may name the user
+    match viewer.user_id {
+        Some(user_id) => job.user_id = user_id,
+        None if !viewer.admin() => {
+            raise_err!(err::ErrorKind::ErrUnauthorized, "Login required.")
+        }
+        None => {}
+    }
     log::info!("job: {:?}", job);
-    let prob = conf.check_prob_and_get(job.problem_id)?;
-    let job_res = PostJobRes::new_with_cases(job.clone(), &prob);
-    let job_res_cloned = job_res.clone();
-    task::spawn(async move {
-        let cases = judge(&job, &conf).unwrap(); // TODO async
-        let prob = conf.check_prob_and_get(job.problem_id).unwrap();
-        let job_res = job_res_cloned.merge(cases, prob);
-        upd_job(job_res.clone()).await.unwrap();
-    });
+    let conf = &state.conf;
+    let job_res = state.try_write(|db| {
+        check_contest(db, &job)?;
+        check_user(db, job.user_id)?;
+        conf.check_lang_and_get(&job.language)?;
+        let prob = db.check_prob_and_get(job.problem_id)?.clone();
+        let job_res = add_job(db, job, &prob);
+        // Queued even if saving fails, the job is in memory either way
+        state.queue.push(job_res.id);
+        Ok(job_res)
+    })?;
     Ok(web::Json(job_res))
 }
+
+// Internal API used by judge-worker
+#[post("/internal/workers/{name}/fetch")]
+pub async fn fetch_task(
+    _: Worker,
+    name: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    while let Some(job_id) = state.queue.try_pop(&name) {
+        if let Some(task) = dispatch(&state, job_id) {
+            log::info!("job {} sent to worker {}", job_id, name);
+            return Ok(HttpResponse::Ok().json(task));
+        }
+        state.queue.finish(job_id, &name);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/internal/workers/{name}/jobs/{job_id}/heartbeat")]
+pub async fn heartbeat(
+    _: Worker,
+    path: web::Path<(String, i32)>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let (name, job_id) = path.into_inner();
+    if !state.queue.heartbeat(job_id, &name) {
+        raise_err!(
+            err::ErrorKind::ErrInvalidState,
+            "Job {} is not assigned to worker {}.",
+            job_id,
+            name
+        )
+    }
+    Ok(web::Json(job_id))
+}
+
+#[post("/internal/workers/{name}/jobs/{job_id}/report")]
+pub async fn report(
+    _: Worker,
+    path: web::Path<(String, i32)>,
+    cases: web::Json<Vec<CaseRes>>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let (name, job_id) = path.into_inner();
+    if !state.queue.finish(job_id, &name) {
+        raise_err!(
+            err::ErrorKind::ErrInvalidState,
+            "Job {} is not assigned to worker {}.",
+            job_id,
+            name
+        )
+    }
+    match complete(&state, job_id, cases.into_inner()) {
+        Some(job_res) => Ok(web::Json(job_res)),
+        None => raise_err!(err::ErrorKind::ErrNotFound, "Job {} not found.", job_id),
+    }
+}
@@ -0,0 +1,76 @@
+use actix_web::{dev::Service, web};
+
+pub mod auth;
+pub mod conf;
+pub mod data;
+pub mod db;
+pub mod err;
+pub mod judge;
+pub mod package;
+pub mod problem;
+pub mod queue;
+pub mod ranklist;
+pub mod rejudge;
+pub mod state;
+pub mod statement;
+pub mod stats;
+pub mod storage;
+pub mod system_test;
+pub mod team;
+pub mod utils;
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::QueryConfig::default().error_handler(err::query_error))
+        .app_data(web::JsonConfig::default().error_handler(err::json_error))
+        .app_data(web::PayloadConfig::new(data::MAX_UPLOAD))
+        .service(
+            web::scope("")
+                .wrap_fn(|req, srv| {
+                    auth::identify(&req);
+                    srv.call(req)
+                })
+                .service(judge::post_jobs)
+                .service(judge::fetch_task)
+                .service(judge::heartbeat)
+                .service(judge::report)
+                .service(db::get_jobs)
+                .service(db::get_job)
+                .service(db::put_job)
+                .service(rejudge::post_rejudge)
+                .service(rejudge::get_rejudge)
+                .service(rejudge::get_report)
+                .service(db::post_user)
+                .service(auth::login)
+                .service(auth::logout)
+                .service(db::get_users)
+                .service(stats::get_user_stats)
+                .service(stats::get_problem_stats)
+                .service(problem::get_problems)
+                .service(problem::get_problem)
+                .service(problem::post_problem)
+                .service(package::post_import)
+                .service(problem::put_problem)
+                .service(problem::delete_problem)
+                .service(statement::get_statement)
+                .service(statement::put_statement)
+                .service(statement::get_image)
+                .service(statement::put_image)
+                .service(statement::delete_image)
+                .service(data::get_data)
+                .service(data::post_data)
+                .service(data::post_order)
+                .service(data::put_case)
+                .service(data::delete_case)
+                .service(team::post_team)
+                .service(team::get_teams)
+                .service(team::get_team)
+                .service(db::post_contest)
+                .service(db::get_contests)
+                .service(db::get_contest)
+                .service(db::register)
+                .service(ranklist::get_ranklist)
+                .service(ranklist::post_unfreeze)
+                .service(ranklist::post_reveal)
+                .service(system_test::get_system_test),
+        );
+}
@@ -1,46 +1,42 @@
-use actix_web::{get, middleware::Logger, post, web, App, HttpServer, Responder};
-use env_logger;
-use log;
-
-mod conf;
-mod db;
-mod err;
-mod judge;
-mod utils;
+use actix_web::{middleware::Logger, post, web, App, HttpServer, Responder};
+use oj::{auth::Admin, conf, judge, package, state::AppState};
 
 // DO NOT REMOVE: used in automatic testing
 #[post("/internal/exit")]
 #[allow(unreachable_code)]
-async fn exit() -> impl Responder {
+async fn exit(_: Admin) -> impl Responder {
     log::info!("Shutdown as requested");
     std::process::exit(0);
-    format!("Exited")
+    "Exited"
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let conf = conf::Conf::parse()?;
     let server = conf.server.clone();
-    db::init_contest(&conf);
-    db::init_user();
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    let command = conf.command.clone();
+    let state = web::Data::new(AppState::new(conf)?);
+    if let Some(conf::Command::Import { dir, id }) = command {
+        return match package::import(&state, std::path::Path::new(&dir), id) {
+            Ok(prob) => {
+                println!("Imported problem {} ({} cases)", prob.id, prob.cases.len());
+                Ok(())
+            }
+            Err(err) => Err(std::io::Error::other(err.to_string())),
+        };
+    }
+    judge::requeue_unfinished(&state);
+    judge::spawn_workers(state.clone().into_inner());
 
     HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(conf.clone()))
+            .app_data(state.clone())
             .wrap(Logger::default())
-            .service(judge::post_jobs)
-            .service(db::get_jobs)
-            .service(db::get_job)
-            .service(db::put_job)
-            .service(db::post_user)
-            .service(db::get_users)
-            .service(db::post_contest)
-            .service(db::get_contests)
-            .service(db::get_contest)
-            .service(db::get_ranklist)
             // DO NOT REMOVE: used in automatic testing
+            // Registered first, the routes catch every path left
             .service(exit)
+            .configure(oj::routes)
     })
     .bind((server.bind_address, server.bind_port))?
     .run()
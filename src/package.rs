@@ -0,0 +1,241 @@
+use crate::{
+    auth::Admin,
+    conf::{Case, Misc, Problem, ProblemType, Statement},
+    data::{self, internal},
+    err,
+    err::raise_err,
+    problem::{add_to_contest0, check_problem, in_use, new_problem_id},
+    state::AppState,
+    statement::statement_dir,
+};
+use actix_web::{post, web, Responder, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// A package directory looks like
+//   problem.yaml (or problem.json)
+//   data/1.in, data/1.ans, data/2.in, ...
+//   checker, named by the manifest
+//   statement/legend.md, input.md, output.md, notes.md and the images
+
+fn default_time_limit() -> i32 {
+    1000000
+}
+fn default_score() -> f64 {
+    100.0
+}
+fn default_checker_command() -> Vec<String> {
+    ["%CHECKER%", "%OUTPUT%", "%ANSWER%"]
+        .map(String::from)
+        .to_vec()
+}
+
+#[derive(Deserialize)]
+pub struct Checker {
+    // Relative to the package
+    pub file: String,
+    #[serde(default = "default_checker_command")]
+    pub command: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub id: Option<i32>,
+    pub name: String,
+    // spj with a checker, standard without one
+    #[serde(default)]
+    pub r#type: Option<ProblemType>,
+    // Limits of every case
+    #[serde(default = "default_time_limit")]
+    pub time_limit: i32,
+    #[serde(default)]
+    pub memory_limit: i32,
+    // Split evenly between the cases
+    #[serde(default = "default_score")]
+    pub score: f64,
+    #[serde(default)]
+    pub checker: Option<Checker>,
+    // packing, pretests, samples, ... as in the config
+    #[serde(flatten)]
+    pub misc: Misc,
+}
+
+fn bad_package(what: impl std::fmt::Display) -> actix_web::Error {
+    err::Error::new(
+        err::ErrorKind::ErrInvalidArgument,
+        format!("Bad package: {}", what),
+    )
+    .into()
+}
+
+fn read_manifest(dir: &Path) -> Result<Manifest> {
+    let yaml = dir.join("problem.yaml");
+    if yaml.exists() {
+        let text = fs::read_to_string(yaml).map_err(internal)?;
+        return serde_yaml::from_str(&text).map_err(bad_package);
+    }
+    let json = dir.join("problem.json");
+    if json.exists() {
+        let text = fs::read_to_string(json).map_err(internal)?;
+        return serde_json::from_str(&text).map_err(bad_package);
+    }
+    Err(bad_package("no problem.yaml or problem.json"))
+}
+
+fn read_data(dir: &Path) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(dir.join("data"))? {
+        let path = entry?.path();
+        if path.is_file() {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            files.insert(name, fs::read(&path)?);
+        }
+    }
+    Ok(files)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        let target = to.join(path.file_name().unwrap());
+        match path.is_dir() {
+            true => copy_dir(&path, &target)?,
+            false => fs::copy(&path, &target).map(|_| ())?,
+        }
+    }
+    Ok(())
+}
+
+// Missing sections are left empty
+fn read_statement(dir: &Path) -> io::Result<Statement> {
+    let read = |name: &str| match fs::read_to_string(dir.join(name)) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        res => res,
+    };
+    Ok(Statement {
+        legend: read("legend.md")?,
+        input_format: read("input.md")?,
+        output_format: read("output.md")?,
+        notes: read("notes.md")?,
+    })
+}
+
+fn store_checker(dir: &Path, package: &Path, checker: &Checker) -> io::Result<Vec<String>> {
+    let path = data::store(dir, &fs::read(package.join(&checker.file))?)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(checker
+        .command
+        .iter()
+        .map(|arg| match arg.as_str() {
+            "%CHECKER%" => path.clone(),
+            _ => arg.clone(),
+        })
+        .collect())
+}
+
+// Loads the package at `package` into problem `id`, or the one named by the
+// manifest, or a new one. An existing problem is replaced unless it is in use.
+pub fn import(state: &AppState, package: &Path, id: Option<i32>) -> Result<Problem> {
+    let manifest = read_manifest(package)?;
+    let pairs = data::pair_files(read_data(package).map_err(bad_package)?)?;
+    if pairs.is_empty() {
+        return Err(bad_package("no data"));
+    }
+    let r#type = match (manifest.r#type, &manifest.checker) {
+        (Some(ProblemType::Spj) | None, Some(_)) => ProblemType::Spj,
+        (Some(_), Some(_)) => return Err(bad_package("a checker needs type spj")),
+        (r#type, None) => r#type.unwrap_or(ProblemType::Standard),
+    };
+    // One write, so nothing takes the id or submits to the problem meanwhile
+    let prob = state.try_write(|db| {
+        let id = match id.or(manifest.id) {
+            Some(id) => id,
+            None => new_problem_id(db),
+        };
+        // Same rule as deleting, jobs and ranklists depend on the problem
+        if db.problem(id).is_some() && in_use(db, id) {
+            raise_err!(
+                err::ErrorKind::ErrInvalidState,
+                "Problem {} is still in use.",
+                id
+            )
+        }
+        let dir = data::problem_dir(&state.conf, id);
+        let score = manifest.score / pairs.len() as f64;
+        let mut cases = vec![];
+        for pair in pairs {
+            cases.push(Case {
+                score,
+                input_file: data::store(&dir, &pair.input).map_err(internal)?,
+                answer_file: data::store(&dir, &pair.answer).map_err(internal)?,
+                time_limit: manifest.time_limit,
+                memory_limit: manifest.memory_limit,
+            });
+        }
+        let mut misc = manifest.misc;
+        if let Some(checker) = &manifest.checker {
+            misc.special_judge = Some(store_checker(&dir, package, checker).map_err(bad_package)?);
+        }
+        let statement = package.join("statement");
+        let prob = Problem {
+            id,
+            name: manifest.name,
+            r#type,
+            misc,
+            cases,
+            statement: match statement.is_dir() {
+                true => Some(read_statement(&statement).map_err(bad_package)?),
+                false => None,
+            },
+        };
+        check_problem(&state.conf, &prob)?;
+        if statement.is_dir() {
+            let target = statement_dir(&state.conf, id);
+            if target.exists() {
+                fs::remove_dir_all(&target).map_err(internal)?;
+            }
+            copy_dir(&statement, &target).map_err(internal)?;
+        }
+        db.put_problem(prob.clone());
+        add_to_contest0(db, id);
+        Ok(prob)
+    })?;
+    let id = prob.id;
+    data::collect_garbage(state, id);
+    log::info!("Imported problem {} from {:?}", id, package);
+    Ok(prob)
+}
+
+#[derive(Deserialize)]
+pub struct ImportBody {
+    // Directory on the server
+    path: String,
+    #[serde(default)]
+    id: Option<i32>,
+}
+
+#[post("/problems/import")]
+pub async fn post_import(
+    _: Admin,
+    body: web::Json<ImportBody>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let path = Path::new(&body.path);
+    if !path.is_dir() {
+        raise_err!(
+            err::ErrorKind::ErrNotFound,
+            "{} is not a directory.",
+            body.path
+        )
+    }
+    Ok(web::Json(import(&state, path, body.id)?))
+}
@@ -0,0 +1,260 @@
+use crate::{
+    auth::{Setter, Viewer},
+    conf::{Conf, Misc, Problem, ProblemType},
+    db::nul_id,
+    err,
+    err::raise_err,
+    judge::State,
+    state::{AppState, Db},
+};
+use actix_web::{delete, get, post, put, web, Responder, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Component, Path};
+
+#[derive(Serialize)]
+struct CaseView {
+    id: i32,
+    score: f64,
+    time_limit: i32,
+    memory_limit: i32,
+    // Data files are only shown to setters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    answer_file: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Sample {
+    pub(crate) id: i32,
+    pub(crate) input: String,
+    pub(crate) answer: String,
+}
+
+#[derive(Serialize)]
+struct ProblemView {
+    id: i32,
+    name: String,
+    r#type: ProblemType,
+    cases: Vec<CaseView>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    samples: Option<Vec<Sample>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    misc: Option<Misc>,
+}
+
+// Unreadable sample files are left out
+pub(crate) fn samples(prob: &Problem) -> Vec<Sample> {
+    let ids = prob.misc.samples.clone().unwrap_or_default();
+    ids.into_iter()
+        .filter_map(|id| {
+            let case = prob.cases.get(usize::try_from(id - 1).ok()?)?;
+            Some(Sample {
+                id,
+                input: fs::read_to_string(&case.input_file).ok()?,
+                answer: fs::read_to_string(&case.answer_file).ok()?,
+            })
+        })
+        .collect()
+}
+
+fn setter(viewer: &Viewer) -> bool {
+    Setter::allows(viewer.role)
+}
+
+// Sample contents are only read for a single problem, lists stay cheap
+fn view(prob: &Problem, viewer: &Viewer, with_samples: bool) -> ProblemView {
+    let full = setter(viewer);
+    let cases = prob.cases.iter().zip(1..).map(|(case, id)| CaseView {
+        id,
+        score: case.score,
+        time_limit: case.time_limit,
+        memory_limit: case.memory_limit,
+        input_file: full.then(|| case.input_file.clone()),
+        answer_file: full.then(|| case.answer_file.clone()),
+    });
+    let samples = with_samples.then(|| samples(prob));
+    ProblemView {
+        id: prob.id,
+        name: prob.name.clone(),
+        r#type: prob.r#type.clone(),
+        cases: cases.collect(),
+        samples,
+        misc: full.then(|| prob.misc.clone()),
+    }
+}
+
+// Case files are read back as samples and fed to the judge, so they have to
+// be in the data directory or be ones the config already uses
+fn known_file(conf: &Conf, file: &str) -> bool {
+    let path = Path::new(file);
+    let in_data_dir =
+        path.starts_with(&conf.data_dir) && !path.components().any(|c| c == Component::ParentDir);
+    in_data_dir
+        || conf
+            .problems
+            .iter()
+            .flat_map(|prob| prob.cases.iter())
+            .any(|case| case.input_file == file || case.answer_file == file)
+}
+
+pub(crate) fn check_problem(conf: &Conf, prob: &Problem) -> Result<()> {
+    use err::ErrorKind::*;
+    if prob.name.is_empty() {
+        raise_err!(ErrInvalidArgument, "Problem name is empty.")
+    }
+    for case in prob.cases.iter() {
+        for file in [&case.input_file, &case.answer_file] {
+            if !known_file(conf, file) {
+                raise_err!(
+                    ErrInvalidArgument,
+                    "Case file {} is not in the data directory.",
+                    file
+                )
+            }
+        }
+    }
+    let misc = &prob.misc;
+    let case_ids = misc
+        .packing
+        .iter()
+        .flatten()
+        .flatten()
+        .chain(misc.pretests.iter().flatten())
+        .chain(misc.samples.iter().flatten());
+    for &id in case_ids {
+        if id < 1 || id as usize > prob.cases.len() {
+            raise_err!(ErrInvalidArgument, "Case {} not found.", id)
+        }
+    }
+    if let ProblemType::Spj = prob.r#type {
+        if misc.special_judge.as_ref().is_none_or(|cmd| cmd.is_empty()) {
+            raise_err!(ErrInvalidArgument, "Special judge command missing.")
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn new_problem_id(db: &Db) -> i32 {
+    db.problems.keys().next_back().map_or(0, |id| id + 1)
+}
+
+// New problems join contest 0 like new users
+pub(crate) fn add_to_contest0(db: &mut Db, id: i32) {
+    let mut contest = db.contest(0).unwrap().clone();
+    if !contest.problem_ids.contains(&id) {
+        contest.problem_ids.push(id);
+        db.put_contest(contest);
+    }
+}
+
+#[get("/problems")]
+pub async fn get_problems(viewer: Viewer, state: web::Data<AppState>) -> Result<impl Responder> {
+    let problems: Vec<ProblemView> = state.read(|db| {
+        db.problems
+            .values()
+            .map(|prob| view(prob, &viewer, false))
+            .collect()
+    });
+    Ok(web::Json(problems))
+}
+
+#[get("/problems/{id}")]
+pub async fn get_problem(
+    id: web::Path<i32>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let prob = state.read(|db| db.check_prob_and_get(*id).cloned())?;
+    Ok(web::Json(view(&prob, &viewer, true)))
+}
+
+#[post("/problems")]
+pub async fn post_problem(
+    _: Setter,
+    prob: web::Json<Problem>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let mut prob = prob.into_inner();
+    check_problem(&state.conf, &prob)?;
+    state.try_write(|db| {
+        if prob.id == nul_id() {
+            prob.id = new_problem_id(db);
+        } else if db.problem(prob.id).is_some() {
+            raise_err!(
+                err::ErrorKind::ErrInvalidArgument,
+                "Problem {} already exists.",
+                prob.id
+            )
+        }
+        db.put_problem(prob.clone());
+        add_to_contest0(db, prob.id);
+        Ok(web::Json(view(&prob, &viewer, false)))
+    })
+}
+
+#[put("/problems/{id}")]
+pub async fn put_problem(
+    _: Setter,
+    id: web::Path<i32>,
+    prob: web::Json<Problem>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let prob = Problem {
+        id: id.into_inner(),
+        ..prob.into_inner()
+    };
+    check_problem(&state.conf, &prob)?;
+    state.try_write(|db| {
+        db.check_prob_and_get(prob.id)?;
+        // Their results are matched against the cases of the problem when done
+        let judging = db.jobs.values().any(|job| {
+            job.submission.problem_id == prob.id
+                && matches!(job.state, State::Queueing | State::Running)
+        });
+        if judging {
+            raise_err!(
+                err::ErrorKind::ErrInvalidState,
+                "Problem {} has jobs being judged.",
+                prob.id
+            )
+        }
+        db.put_problem(prob.clone());
+        Ok(web::Json(view(&prob, &viewer, false)))
+    })
+}
+
+// Problems that were submitted to or are in a contest are kept, jobs and ranklists need them
+pub(crate) fn in_use(db: &Db, id: i32) -> bool {
+    let in_contest = db.contests[1..]
+        .iter()
+        .any(|contest| contest.problem_ids.contains(&id));
+    in_contest || db.jobs.values().any(|job| job.submission.problem_id == id)
+}
+
+#[delete("/problems/{id}")]
+pub async fn delete_problem(
+    _: Setter,
+    id: web::Path<i32>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    state.try_write(|db| {
+        let prob = db.check_prob_and_get(id)?.clone();
+        if in_use(db, id) {
+            raise_err!(
+                err::ErrorKind::ErrInvalidState,
+                "Problem {} is still in use.",
+                id
+            )
+        }
+        db.remove_problem(id);
+        let mut contest = db.contest(0).unwrap().clone();
+        contest.problem_ids.retain(|&p| p != id);
+        db.put_contest(contest);
+        Ok(web::Json(prob))
+    })
+}
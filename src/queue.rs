@@ -0,0 +1,112 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+struct Lease {
+    worker: String,
+    // None for workers living in this process, which never go silent
+    beat: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Inner {
+    pending: VecDeque<i32>,
+    running: HashMap<i32, Lease>,
+}
+
+impl Inner {
+    fn take(&mut self, worker: &str, beat: Option<Instant>) -> Option<i32> {
+        let job_id = self.pending.pop_front()?;
+        let lease = Lease {
+            worker: worker.to_string(),
+            beat,
+        };
+        self.running.insert(job_id, lease);
+        Some(job_id)
+    }
+
+    fn owns(&self, job_id: i32, worker: &str) -> bool {
+        matches!(self.running.get(&job_id), Some(lease) if lease.worker == worker)
+    }
+}
+
+#[derive(Default)]
+pub struct Queue {
+    inner: Mutex<Inner>,
+    ready: Condvar,
+}
+
+impl Queue {
+    pub fn push(&self, job_id: i32) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.pending.contains(&job_id) {
+            inner.pending.push_back(job_id);
+        }
+        self.ready.notify_one();
+    }
+
+    // For remote workers: returns immediately
+    pub fn try_pop(&self, worker: &str) -> Option<i32> {
+        self.inner
+            .lock()
+            .unwrap()
+            .take(worker, Some(Instant::now()))
+    }
+
+    // For local workers: blocks until a job is available
+    pub fn wait_pop(&self, worker: &str) -> i32 {
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            if let Some(job_id) = inner.take(worker, None) {
+                return job_id;
+            }
+            inner = self.ready.wait(inner).unwrap();
+        }
+    }
+
+    pub fn heartbeat(&self, job_id: i32, worker: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.owns(job_id, worker) {
+            return false;
+        }
+        if let Some(lease) = inner.running.get_mut(&job_id) {
+            lease.beat = lease.beat.map(|_| Instant::now());
+        }
+        true
+    }
+
+    pub fn finish(&self, job_id: i32, worker: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.owns(job_id, worker) {
+            return false;
+        }
+        inner.running.remove(&job_id);
+        true
+    }
+
+    // Jobs handed to a worker and not finished yet
+    pub fn running(&self) -> Vec<i32> {
+        self.inner.lock().unwrap().running.keys().copied().collect()
+    }
+
+    // Put jobs whose worker stopped sending heartbeats back to the front
+    pub fn requeue_expired(&self, timeout: Duration) -> Vec<i32> {
+        let mut inner = self.inner.lock().unwrap();
+        let expired: Vec<i32> = inner
+            .running
+            .iter()
+            .filter(|(_, lease)| matches!(lease.beat, Some(beat) if beat.elapsed() > timeout))
+            .map(|(&job_id, _)| job_id)
+            .collect();
+        for job_id in expired.iter().rev() {
+            if let Some(lease) = inner.running.remove(job_id) {
+                log::warn!("worker {} lost job {}", lease.worker, job_id);
+            }
+            inner.pending.push_front(*job_id);
+        }
+        if !expired.is_empty() {
+            self.ready.notify_all();
+        }
+        expired
+    }
+}
@@ -0,0 +1,454 @@
+use crate::{
+    auth::{Admin, Viewer},
+    db::{Contest, PostJobRes, User},
+    err,
+    err::raise_err,
+    judge::{CaseResult, State},
+    state::{AppState, Db},
+    team::Team,
+    utils::apmax,
+};
+use actix_web::{get, http::header, post, web, HttpRequest, HttpResponse, Responder, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+// Set per contest, "score" ranks by the sum of scores under the requested rule
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoringMode {
+    #[default]
+    Score,
+    Icpc,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoringRule {
+    #[default]
+    Latest,
+    Highest,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreaker {
+    SubmissionTime,
+    SubmissionCount,
+    UserId,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+pub struct RankRule {
+    #[serde(default)]
+    scoring_rule: ScoringRule,
+    tie_breaker: Option<TieBreaker>,
+}
+
+// One column of an ICPC row, solve_time is in minutes from the contest start
+#[derive(Clone, Serialize)]
+pub struct ProblemRank {
+    problem_id: i32,
+    attempts: i32,
+    // Not judged yet, or hidden by the freeze
+    pending: i32,
+    solve_time: Option<i64>,
+}
+
+// A user, or a team in team contests
+#[derive(Clone, Serialize)]
+pub struct UserRank {
+    #[serde(skip)]
+    id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<User>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    team: Option<Team>,
+    rank: i32,
+    #[serde(skip)]
+    sub_cnt: i32,
+    #[serde(skip)]
+    sub_time: DateTime<Utc>,
+    scores: Vec<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    solved: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    penalty: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    problems: Option<Vec<ProblemRank>>,
+}
+
+impl UserRank {
+    // User id, or team id in team contests
+    pub(crate) fn id(&self) -> i32 {
+        self.id
+    }
+    pub(crate) fn rank(&self) -> i32 {
+        self.rank
+    }
+}
+
+// Rows are kept per user or team, only rows touched since the last request are recomputed
+#[derive(Default)]
+struct Standing {
+    rows: BTreeMap<i32, UserRank>,
+    stale: BTreeSet<i32>,
+    // Ranked rows and their ETag, dropped whenever a row changes
+    ranked: Option<(Arc<Vec<UserRank>>, String)>,
+}
+
+// What a viewer may see of the jobs in a ranklist
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum View {
+    Live,
+    // Jobs after the freeze time are pending
+    Frozen,
+    // OI contest still running, every job is pending
+    Sealed,
+}
+
+// Standings of every (contest, rule, view) asked for so far, kept up to date by Db
+#[derive(Default)]
+pub struct Cache(HashMap<(i32, RankRule, View), Standing>);
+
+impl Cache {
+    // entrant is the row of user_id in the contest, if it has one
+    pub(crate) fn job_changed(&mut self, contest_id: i32, user_id: i32, entrant: Option<i32>) {
+        for ((id, _, _), standing) in self.0.iter_mut() {
+            let row = match *id {
+                // Contest 0 counts submissions to every contest
+                0 => Some(user_id),
+                id if id == contest_id => entrant,
+                _ => continue,
+            };
+            standing.stale.extend(row);
+            standing.ranked = None;
+        }
+    }
+    pub(crate) fn contest_changed(&mut self, contest_id: i32) {
+        self.0.retain(|(id, _, _), _| *id != contest_id);
+    }
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+// Jobs of a row on one problem in submission order
+fn problem_jobs<'a>(
+    db: &'a Db,
+    contest: &Contest,
+    problem_id: i32,
+    members: &[i32],
+) -> Vec<&'a PostJobRes> {
+    // Contest 0 counts submissions to every contest
+    let contests: Vec<i32> = match contest.id {
+        0 => db.contests.iter().map(|c| c.id).collect(),
+        id => vec![id],
+    };
+    let mut jobs: Vec<_> = contests
+        .iter()
+        .flat_map(|&c| {
+            members
+                .iter()
+                .flat_map(move |&u| db.jobs_on(c, problem_id, u))
+        })
+        .collect();
+    jobs.sort_by_key(|job| job.id);
+    jobs
+}
+
+fn user_row(db: &Db, contest: &Contest, id: i32, rule: &RankRule, view: View) -> UserRank {
+    let hidden = |job| match view {
+        View::Live => false,
+        View::Frozen => contest.hides(job),
+        View::Sealed => true,
+    };
+    let members = db.members(contest, id);
+    let (user, team) = match contest.team_contest {
+        true => (None, db.team(id).cloned()),
+        false => (db.user(id).cloned(), None),
+    };
+    let mut row = UserRank {
+        id,
+        user,
+        team,
+        rank: 0,
+        sub_cnt: 0,
+        // Users without submissions go last
+        sub_time: DateTime::<Utc>::MAX_UTC,
+        scores: vec![],
+        solved: None,
+        penalty: None,
+        problems: None,
+    };
+    let mut sub_time = None;
+    match contest.scoring_mode {
+        ScoringMode::Score => {
+            for &problem_id in contest.problem_ids.iter() {
+                let mut score = None;
+                for job in problem_jobs(db, contest, problem_id, &members) {
+                    if hidden(job) {
+                        continue;
+                    }
+                    let updated = match (score, rule.scoring_rule) {
+                        (None, _) | (_, ScoringRule::Latest) => true,
+                        (Some(s), ScoringRule::Highest) => s < job.score,
+                    };
+                    row.sub_cnt += 1;
+                    if updated {
+                        score = Some(job.score);
+                        apmax(&mut sub_time, Some(job.created_time));
+                    }
+                }
+                row.scores.push(score.unwrap_or_default());
+            }
+        }
+        ScoringMode::Icpc => {
+            let (mut solved, mut penalty, mut problems) = (0, 0, vec![]);
+            for &problem_id in contest.problem_ids.iter() {
+                let mut problem = ProblemRank {
+                    problem_id,
+                    attempts: 0,
+                    pending: 0,
+                    solve_time: None,
+                };
+                let mut score = 0f64;
+                for job in problem_jobs(db, contest, problem_id, &members) {
+                    row.sub_cnt += 1;
+                    if job.state != State::Finished || hidden(job) {
+                        problem.pending += 1;
+                        continue;
+                    }
+                    // Judge failures count neither way
+                    if let CaseResult::CompilationError | CaseResult::SystemError = job.result {
+                        continue;
+                    }
+                    problem.attempts += 1;
+                    if job.result == CaseResult::Accepted {
+                        let minutes = (job.created_time - contest.from).num_minutes();
+                        problem.solve_time = Some(minutes);
+                        (solved, score) = (solved + 1, job.score);
+                        penalty += minutes + 20 * (problem.attempts as i64 - 1);
+                        apmax(&mut sub_time, Some(job.created_time));
+                        break;
+                    }
+                }
+                row.scores.push(score);
+                problems.push(problem);
+            }
+            (row.solved, row.penalty) = (Some(solved), Some(penalty));
+            row.problems = Some(problems);
+        }
+    }
+    if let Some(sub_time) = sub_time {
+        row.sub_time = sub_time;
+    }
+    row
+}
+
+fn rank_cmp(a: &UserRank, b: &UserRank, tie_breaker: Option<TieBreaker>) -> Ordering {
+    let res = match (a.solved, b.solved) {
+        // More problems solved, then less penalty
+        (Some(x), Some(y)) => y.cmp(&x).then(a.penalty.cmp(&b.penalty)),
+        _ => {
+            let total = |r: &UserRank| r.scores.iter().sum::<f64>();
+            total(b).partial_cmp(&total(a)).unwrap()
+        }
+    };
+    res.then_with(|| match tie_breaker {
+        None => Ordering::Equal,
+        Some(TieBreaker::SubmissionTime) => a.sub_time.cmp(&b.sub_time),
+        Some(TieBreaker::SubmissionCount) => a.sub_cnt.cmp(&b.sub_cnt),
+        Some(TieBreaker::UserId) => a.id.cmp(&b.id),
+    })
+}
+
+fn rank(rows: Vec<UserRank>, rule: &RankRule) -> (Arc<Vec<UserRank>>, String) {
+    let mut res = rows;
+    // Stable sort, rows come ordered by id
+    res.sort_by(|a, b| rank_cmp(a, b, rule.tie_breaker));
+    for i in 0..res.len() {
+        res[i].rank = match i > 0 && rank_cmp(&res[i], &res[i - 1], rule.tie_breaker).is_eq() {
+            true => res[i - 1].rank,
+            false => i as i32 + 1,
+        };
+    }
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_vec(&res).unwrap().hash(&mut hasher);
+    (Arc::new(res), format!("\"{:016x}\"", hasher.finish()))
+}
+
+// Admins always see live results, everyone else may get a frozen or sealed board
+pub fn standings(
+    db: &Db,
+    id: i32,
+    rule: &RankRule,
+    admin: bool,
+) -> Result<(Arc<Vec<UserRank>>, String)> {
+    let contest = match db.contest(id) {
+        Some(contest) => contest,
+        None => raise_err!(err::ErrorKind::ErrNotFound, "Contest {} not found.", id),
+    };
+    let view = if admin {
+        View::Live
+    } else if contest.sealed() {
+        View::Sealed
+    } else if contest.freeze_time.is_some() && !contest.unfrozen {
+        View::Frozen
+    } else {
+        View::Live
+    };
+    let mut cache = db.ranklists.lock().unwrap();
+    let standing = cache
+        .0
+        .entry((id, *rule, view))
+        .or_insert_with(|| Standing {
+            rows: db
+                .entrants(contest)
+                .into_iter()
+                .map(|row_id| (row_id, user_row(db, contest, row_id, rule, view)))
+                .collect(),
+            ..Default::default()
+        });
+    for row_id in std::mem::take(&mut standing.stale) {
+        if let Some(row) = standing.rows.get_mut(&row_id) {
+            *row = user_row(db, contest, row_id, rule, view);
+        }
+    }
+    let ranked = standing
+        .ranked
+        .get_or_insert_with(|| rank(standing.rows.values().cloned().collect(), rule));
+    Ok(ranked.clone())
+}
+
+#[get("/contests/{contest_id}/ranklist")]
+pub async fn get_ranklist(
+    contest_id: web::Path<i32>,
+    rule: web::Query<RankRule>,
+    req: HttpRequest,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (rows, etag) = state.read(|db| {
+        // Same as GET /contests/{id}, private contests don't exist for outsiders
+        if db
+            .contest(*contest_id)
+            .is_some_and(|contest| !contest.shown_to(db, &viewer))
+        {
+            raise_err!(
+                err::ErrorKind::ErrNotFound,
+                "Contest {} not found.",
+                contest_id
+            )
+        }
+        standings(db, *contest_id, &rule, viewer.admin())
+    })?;
+    let unchanged = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*")
+        });
+    let mut res = match unchanged {
+        true => HttpResponse::NotModified(),
+        false => HttpResponse::Ok(),
+    };
+    res.insert_header((header::ETAG, etag));
+    Ok(match unchanged {
+        true => res.finish(),
+        false => res.json(&*rows),
+    })
+}
+
+#[post("/contests/{contest_id}/unfreeze")]
+pub async fn post_unfreeze(
+    contest_id: web::Path<i32>,
+    _: Admin,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let id = contest_id.into_inner();
+    state.try_write(|db| {
+        let mut contest = match db.contest(id) {
+            Some(contest) => contest.clone(),
+            None => raise_err!(err::ErrorKind::ErrNotFound, "Contest {} not found.", id),
+        };
+        contest.unfrozen = true;
+        db.put_contest(contest.clone());
+        Ok(web::Json(contest))
+    })
+}
+
+#[derive(Serialize)]
+struct Reveal {
+    user_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    team_id: Option<i32>,
+    problem_id: Option<i32>,
+    ranklist: Vec<UserRank>,
+}
+
+// Shows one hidden cell per call, starting from the bottom of the frozen board.
+// Once nothing is left the contest is unfrozen.
+#[post("/contests/{contest_id}/reveal")]
+pub async fn post_reveal(
+    contest_id: web::Path<i32>,
+    rule: web::Query<RankRule>,
+    _: Admin,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let id = contest_id.into_inner();
+    state.try_write(|db| {
+        let mut contest = match db.contest(id) {
+            Some(contest) => contest.clone(),
+            None => raise_err!(err::ErrorKind::ErrNotFound, "Contest {} not found.", id),
+        };
+        if contest.freeze_time.is_none() || contest.unfrozen {
+            raise_err!(
+                err::ErrorKind::ErrInvalidState,
+                "Contest {} is not frozen.",
+                id
+            );
+        }
+        if Utc::now() < contest.to {
+            raise_err!(
+                err::ErrorKind::ErrInvalidState,
+                "Contest {} has not ended.",
+                id
+            );
+        }
+        let (board, _) = standings(db, id, &rule, false)?;
+        let next = board.iter().rev().find_map(|row| {
+            let members = db.members(&contest, row.id);
+            contest
+                .problem_ids
+                .iter()
+                .find(|&&p| {
+                    members
+                        .iter()
+                        .any(|&u| db.jobs_on(id, p, u).any(|job| contest.hides(job)))
+                })
+                .map(|&p| (row.id, p, members))
+        });
+        // A team cell is shown for every member at once
+        match &next {
+            Some((_, p, members)) => contest.revealed.extend(members.iter().map(|&u| (u, *p))),
+            None => contest.unfrozen = true,
+        }
+        let team = contest.team_contest;
+        db.put_contest(contest);
+        let (ranklist, _) = standings(db, id, &rule, false)?;
+        Ok(web::Json(Reveal {
+            user_id: next.as_ref().filter(|_| !team).map(|(u, _, _)| *u),
+            team_id: next.as_ref().filter(|_| team).map(|(t, _, _)| *t),
+            problem_id: next.as_ref().map(|(_, p, _)| *p),
+            ranklist: ranklist.to_vec(),
+        }))
+    })
+}
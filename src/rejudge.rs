@@ -0,0 +1,246 @@
+use crate::{
+    auth::{Admin, Viewer},
+    data,
+    db::{filter_jobs, set_job_state, JobQuery, PostJobRes, User},
+    err,
+    err::raise_err,
+    judge::{judge_safe, CaseResult, State},
+    state::{AppState, Db},
+    utils::rfc3339,
+};
+use actix_web::{get, post, web, Responder, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Clone, Serialize)]
+struct Entry {
+    job_id: i32,
+    user_id: i32,
+    old_result: CaseResult,
+    old_score: f64,
+    new_result: Option<CaseResult>,
+    new_score: Option<f64>,
+}
+
+impl Entry {
+    fn new(job: &PostJobRes) -> Self {
+        Self {
+            job_id: job.id,
+            user_id: job.submission.user_id,
+            old_result: job.result,
+            old_score: job.score,
+            new_result: None,
+            new_score: None,
+        }
+    }
+    fn fill(&mut self, job: &PostJobRes) {
+        (self.new_result, self.new_score) = (Some(job.result), Some(job.score));
+    }
+    fn changed(&self) -> bool {
+        self.new_result.is_some_and(|res| res != self.old_result)
+            || self.new_score.is_some_and(|score| score != self.old_score)
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct Rejudge {
+    id: i32,
+    #[serde(with = "rfc3339")]
+    created_time: DateTime<Utc>,
+    dry_run: bool,
+    job_ids: Vec<i32>,
+    state: State,
+    total: usize,
+    finished: usize,
+    #[serde(skip)]
+    entries: Vec<Entry>,
+}
+
+impl Rejudge {
+    fn new(id: i32, dry_run: bool, entries: Vec<Entry>) -> Self {
+        Self {
+            id,
+            created_time: Utc::now(),
+            dry_run,
+            job_ids: entries.iter().map(|entry| entry.job_id).collect(),
+            state: State::Queueing,
+            total: entries.len(),
+            finished: 0,
+            entries,
+        }
+    }
+
+    fn refresh(&mut self, db: &Db) {
+        if !self.dry_run {
+            // Keep the first result seen, later rejudges get their own report
+            for entry in self.entries.iter_mut().filter(|e| e.new_result.is_none()) {
+                if let Some(job) = db.job(entry.job_id) {
+                    if job.state == State::Finished {
+                        entry.fill(job);
+                    }
+                }
+            }
+        }
+        self.finished = self
+            .entries
+            .iter()
+            .filter(|entry| entry.new_result.is_some())
+            .count();
+        self.state = match self.finished == self.total {
+            true => State::Finished,
+            false => State::Running,
+        };
+    }
+}
+
+fn push(state: &AppState, dry_run: bool, entries: Vec<Entry>) -> Rejudge {
+    let mut rejudges = state.rejudges.lock().unwrap();
+    let mut rejudge = Rejudge::new(rejudges.len() as i32, dry_run, entries);
+    state.read(|db| rejudge.refresh(db));
+    log::info!("rejudge {}: {} jobs", rejudge.id, rejudge.total);
+    rejudges.push(rejudge.clone());
+    rejudge
+}
+
+// Single jobs rejudged by PUT /jobs/{id} get a report as well
+pub fn record(state: &AppState, old_job: &PostJobRes, new_job: &PostJobRes) {
+    let mut entry = Entry::new(old_job);
+    entry.fill(new_job);
+    push(state, false, vec![entry]);
+}
+
+// Judge again without touching the stored jobs, results only go to the report
+fn dry_run(state: Arc<AppState>, id: i32, jobs: Vec<PostJobRes>) {
+    std::thread::spawn(move || {
+        let conf = &state.conf;
+        for job in jobs {
+            let sub = &job.submission;
+            let _reading = data::reading(&state, sub.problem_id);
+            let new_job = match (
+                conf.check_lang_and_get(&sub.language),
+                state.read(|db| db.problem(sub.problem_id).cloned()),
+            ) {
+                (Ok(lang), Some(prob)) => {
+                    let cases = judge_safe(sub, lang, &prob, job.pretest);
+                    job.clone().merge(cases, &prob)
+                }
+                // As the real rejudge would, so the batch still finishes
+                _ => job.clone().fail("language or problem no longer exists"),
+            };
+            let mut rejudges = state.rejudges.lock().unwrap();
+            let rejudge = &mut rejudges[id as usize];
+            if let Some(entry) = rejudge.entries.iter_mut().find(|e| e.job_id == job.id) {
+                entry.fill(&new_job);
+            }
+            state.read(|db| rejudge.refresh(db));
+        }
+    });
+}
+
+#[derive(Deserialize)]
+pub struct RejudgeQuery {
+    #[serde(flatten)]
+    filter: JobQuery,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[post("/rejudge")]
+pub async fn post_rejudge(
+    _: Admin,
+    info: web::Json<RejudgeQuery>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    // Jobs still in the queue will see the new data anyway
+    let finished = |db: &Db| -> Vec<PostJobRes> {
+        filter_jobs(db, &info.filter, &Viewer::root())
+            .into_iter()
+            .filter(|job| job.state == State::Finished)
+            .map(|job| job.into_owned())
+            .collect()
+    };
+    if info.dry_run {
+        let jobs = state.read(finished);
+        let rejudge = push(&state, true, jobs.iter().map(Entry::new).collect());
+        dry_run(state.into_inner(), rejudge.id, jobs);
+        return Ok(web::Json(rejudge));
+    }
+    let mut jobs = vec![];
+    // Queued even if saving fails, the jobs are requeued in memory either way
+    let saved = state.write(|db| {
+        jobs = finished(db);
+        for job in jobs.iter() {
+            set_job_state(db, job.id, State::Queueing);
+        }
+    });
+    let rejudge = push(&state, false, jobs.iter().map(Entry::new).collect());
+    for job in jobs.iter() {
+        state.queue.push(job.id);
+    }
+    saved?;
+    Ok(web::Json(rejudge))
+}
+
+fn get_and_refresh(state: &AppState, id: i32) -> Result<Rejudge> {
+    let mut rejudges = state.rejudges.lock().unwrap();
+    match rejudges.get_mut(id as usize) {
+        Some(rejudge) => {
+            state.read(|db| rejudge.refresh(db));
+            Ok(rejudge.clone())
+        }
+        None => raise_err!(err::ErrorKind::ErrNotFound, "Rejudge {} not found.", id),
+    }
+}
+
+#[get("/rejudge/{id}")]
+pub async fn get_rejudge(
+    _: Admin,
+    id: web::Path<i32>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    Ok(web::Json(get_and_refresh(&state, id.into_inner())?))
+}
+
+#[derive(Serialize)]
+struct Change {
+    job_id: i32,
+    user: Option<User>,
+    old_result: CaseResult,
+    old_score: f64,
+    new_result: Option<CaseResult>,
+    new_score: Option<f64>,
+    changed: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ReportQuery {
+    #[serde(default)]
+    changed_only: bool,
+}
+
+#[get("/rejudge/{id}/report")]
+pub async fn get_report(
+    _: Admin,
+    id: web::Path<i32>,
+    info: web::Query<ReportQuery>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let rejudge = get_and_refresh(&state, id.into_inner())?;
+    let users = state.read(|db| db.users.clone());
+    let changes: Vec<Change> = rejudge
+        .entries
+        .iter()
+        .filter(|entry| !info.changed_only || entry.changed())
+        .map(|entry| Change {
+            job_id: entry.job_id,
+            user: users.get(entry.user_id as usize).cloned(),
+            old_result: entry.old_result,
+            old_score: entry.old_score,
+            new_result: entry.new_result,
+            new_score: entry.new_score,
+            changed: entry.changed(),
+        })
+        .collect();
+    Ok(web::Json(changes))
+}
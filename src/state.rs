@@ -0,0 +1,313 @@
+use crate::{
+    auth::Credential,
+    conf::{Conf, Problem},
+    data::Garbage,
+    db::{init_contest, init_problems, init_user, Contest, PostJobRes, User},
+    err,
+    queue::Queue,
+    ranklist,
+    rejudge::Rejudge,
+    storage::{self, Record, Storage},
+    team::Team,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::sync::{Mutex, RwLock};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Dirty {
+    Job(i32),
+    User(i32),
+    Contest(i32),
+    Team(i32),
+    Problem(i32),
+    Credential(i32),
+}
+
+// Jobs, users and contests, only reachable through AppState::read and AppState::write
+#[derive(Default)]
+pub struct Db {
+    pub(crate) jobs: BTreeMap<i32, PostJobRes>,
+    pub(crate) users: Vec<User>,
+    pub(crate) contests: Vec<Contest>,
+    pub(crate) teams: Vec<Team>,
+    pub(crate) problems: BTreeMap<i32, Problem>,
+    credentials: BTreeMap<i32, Credential>,
+    // Job ids in increasing order, keyed by (contest, user) and (contest, problem, user)
+    by_user: BTreeMap<(i32, i32), Vec<i32>>,
+    by_problem: BTreeMap<(i32, i32, i32), Vec<i32>>,
+    pub(crate) ranklists: Mutex<ranklist::Cache>,
+    dirty: BTreeSet<Dirty>,
+}
+
+impl Db {
+    pub fn job(&self, id: i32) -> Option<&PostJobRes> {
+        self.jobs.get(&id)
+    }
+    // Submissions never change once posted, so only new jobs touch the indexes
+    pub fn put_job(&mut self, job: PostJobRes) {
+        let id = job.id;
+        let sub = &job.submission;
+        let (c, p, u) = (sub.contest_id, sub.problem_id, sub.user_id);
+        let entrant = self.contest(c).and_then(|c| self.entrant_of(c, u));
+        self.dirty.insert(Dirty::Job(id));
+        self.ranklists.get_mut().unwrap().job_changed(c, u, entrant);
+        if self.jobs.insert(id, job).is_none() {
+            self.by_user.entry((c, u)).or_default().push(id);
+            self.by_problem.entry((c, p, u)).or_default().push(id);
+        }
+    }
+    pub fn jobs_of(&self, contest: i32, user: i32) -> impl Iterator<Item = &PostJobRes> {
+        self.indexed(self.by_user.get(&(contest, user)))
+    }
+    pub fn jobs_on(
+        &self,
+        contest: i32,
+        problem: i32,
+        user: i32,
+    ) -> impl Iterator<Item = &PostJobRes> {
+        self.indexed(self.by_problem.get(&(contest, problem, user)))
+    }
+    fn indexed<'a>(&'a self, ids: Option<&'a Vec<i32>>) -> impl Iterator<Item = &'a PostJobRes> {
+        ids.into_iter().flatten().filter_map(|id| self.jobs.get(id))
+    }
+
+    pub fn user(&self, id: i32) -> Option<&User> {
+        usize::try_from(id).ok().and_then(|id| self.users.get(id))
+    }
+    // Ids are indexes: a user with id == len is appended
+    pub fn put_user(&mut self, user: User) {
+        let id = user.id;
+        self.dirty.insert(Dirty::User(id));
+        // Names show up in every ranklist
+        self.ranklists.get_mut().unwrap().clear();
+        match self.users.get_mut(id as usize) {
+            Some(entry) => *entry = user,
+            None => self.users.push(user),
+        }
+    }
+
+    pub fn contest(&self, id: i32) -> Option<&Contest> {
+        usize::try_from(id)
+            .ok()
+            .and_then(|id| self.contests.get(id))
+    }
+    pub fn put_contest(&mut self, contest: Contest) {
+        let id = contest.id;
+        self.dirty.insert(Dirty::Contest(id));
+        self.ranklists.get_mut().unwrap().contest_changed(id);
+        match self.contests.get_mut(id as usize) {
+            Some(entry) => *entry = contest,
+            None => self.contests.push(contest),
+        }
+    }
+
+    pub fn team(&self, id: i32) -> Option<&Team> {
+        usize::try_from(id).ok().and_then(|id| self.teams.get(id))
+    }
+    pub fn put_team(&mut self, team: Team) {
+        let id = team.id;
+        self.dirty.insert(Dirty::Team(id));
+        self.ranklists.get_mut().unwrap().clear();
+        match self.teams.get_mut(id as usize) {
+            Some(entry) => *entry = team,
+            None => self.teams.push(team),
+        }
+    }
+
+    pub fn problem(&self, id: i32) -> Option<&Problem> {
+        self.problems.get(&id)
+    }
+    pub fn check_prob_and_get(&self, id: i32) -> Result<&Problem, err::Error> {
+        match self.problem(id) {
+            Some(prob) => Ok(prob),
+            None => err::raise_err!(err::ErrorKind::ErrNotFound, "Problem {} not found.", id),
+        }
+    }
+    pub fn put_problem(&mut self, problem: Problem) {
+        self.dirty.insert(Dirty::Problem(problem.id));
+        self.problems.insert(problem.id, problem);
+    }
+    pub fn remove_problem(&mut self, id: i32) {
+        self.dirty.insert(Dirty::Problem(id));
+        self.problems.remove(&id);
+    }
+
+    // Ranklist rows of a contest: its teams in team contests, its users otherwise
+    pub fn entrants(&self, contest: &Contest) -> Vec<i32> {
+        match (contest.id, contest.team_contest) {
+            (0, _) => (0..self.users.len() as i32).collect(),
+            (_, true) => contest.team_ids.clone(),
+            (_, false) => contest.user_ids.clone(),
+        }
+    }
+    pub fn entrant_of(&self, contest: &Contest, user_id: i32) -> Option<i32> {
+        match contest.team_contest {
+            true => contest.team_ids.iter().copied().find(|&id| {
+                self.team(id)
+                    .is_some_and(|team| team.user_ids.contains(&user_id))
+            }),
+            false => contest.user_ids.contains(&user_id).then_some(user_id),
+        }
+    }
+    pub fn members(&self, contest: &Contest, entrant: i32) -> Vec<i32> {
+        match contest.team_contest {
+            true => self
+                .team(entrant)
+                .map_or(vec![], |team| team.user_ids.clone()),
+            false => vec![entrant],
+        }
+    }
+
+    pub fn credential(&self, user_id: i32) -> Option<&Credential> {
+        self.credentials.get(&user_id)
+    }
+    pub fn put_credential(&mut self, cred: Credential) {
+        self.dirty.insert(Dirty::Credential(cred.id));
+        self.credentials.insert(cred.id, cred);
+    }
+}
+
+// Users, contests and teams are looked up by position, so their ids must be 0, 1, 2, ...
+fn by_index<T: Record>(table: &str, records: Vec<T>) -> io::Result<Vec<T>> {
+    match records
+        .iter()
+        .zip(0..)
+        .find(|(record, i)| record.id() != *i)
+    {
+        Some((record, i)) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: expected id {}, found {}", table, i, record.id()),
+        )),
+        None => Ok(records),
+    }
+}
+
+// Everything one server instance owns. Locks are only taken through the methods
+// here; rejudges, when needed, is always locked before db, and db before the
+// ranklist cache inside it.
+pub struct AppState {
+    pub conf: Conf,
+    pub queue: Queue,
+    pub(crate) rejudges: Mutex<Vec<Rejudge>>,
+    // Login tokens and their users, gone after a restart
+    pub(crate) sessions: Mutex<HashMap<String, i32>>,
+    pub(crate) garbage: Mutex<Garbage>,
+    db: RwLock<Db>,
+    storage: Box<dyn Storage>,
+}
+
+impl AppState {
+    pub fn new(conf: Conf) -> io::Result<Self> {
+        let storage = storage::open(&conf.storage)?;
+        if conf.flush_data {
+            log::info!("Flush data");
+            storage.flush()?;
+        }
+        let snapshot = storage.load()?;
+        log::info!(
+            "Loaded {} jobs, {} users, {} contests, {} teams",
+            snapshot.jobs.len(),
+            snapshot.users.len(),
+            snapshot.contests.len(),
+            snapshot.teams.len()
+        );
+        let mut db = Db {
+            users: by_index("users", snapshot.users)?,
+            contests: by_index("contests", snapshot.contests)?,
+            teams: by_index("teams", snapshot.teams)?,
+            problems: snapshot
+                .problems
+                .into_iter()
+                .map(|prob| (prob.id, prob))
+                .collect(),
+            credentials: snapshot
+                .credentials
+                .into_iter()
+                .map(|cred| (cred.id, cred))
+                .collect(),
+            ..Default::default()
+        };
+        let mut jobs = snapshot.jobs;
+        jobs.sort_by_key(|job| job.id);
+        for job in jobs {
+            db.put_job(job);
+        }
+        db.dirty.clear();
+        let state = Self {
+            conf,
+            queue: Queue::default(),
+            rejudges: Mutex::new(Vec::new()),
+            sessions: Mutex::new(HashMap::new()),
+            garbage: Mutex::default(),
+            db: RwLock::new(db),
+            storage,
+        };
+        let (_, failed) = state.apply(|db| {
+            init_problems(db, &state.conf);
+            init_contest(db);
+            init_user(db, &state.conf);
+        });
+        match failed {
+            Some(err) => Err(err),
+            None => Ok(state),
+        }
+    }
+
+    pub fn read<R>(&self, f: impl FnOnce(&Db) -> R) -> R {
+        f(&self.db.read().unwrap())
+    }
+
+    // Records put by f are saved before any other reader sees them
+    fn apply<R>(&self, f: impl FnOnce(&mut Db) -> R) -> (R, Option<io::Error>) {
+        let mut db = self.db.write().unwrap();
+        let res = f(&mut db);
+        let mut failed = None;
+        for dirty in std::mem::take(&mut db.dirty) {
+            let saved = match dirty {
+                Dirty::Job(id) => db.job(id).map(|job| self.storage.save_job(job)),
+                Dirty::User(id) => db.user(id).map(|user| self.storage.save_user(user)),
+                Dirty::Contest(id) => db.contest(id).map(|c| self.storage.save_contest(c)),
+                Dirty::Problem(id) => Some(match db.problem(id) {
+                    Some(prob) => self.storage.save_problem(prob),
+                    None => self.storage.remove_problem(id),
+                }),
+                Dirty::Team(id) => db.team(id).map(|team| self.storage.save_team(team)),
+                Dirty::Credential(id) => db
+                    .credential(id)
+                    .map(|cred| self.storage.save_credential(cred)),
+            };
+            if let Some(Err(err)) = saved {
+                log::error!("storage: {}", err);
+                failed.get_or_insert(err);
+            }
+        }
+        (res, failed)
+    }
+
+    // If a record can't be saved the change only lives in memory, and the
+    // caller is told
+    pub fn write<R>(&self, f: impl FnOnce(&mut Db) -> R) -> actix_web::Result<R> {
+        match self.apply(f) {
+            (res, None) => Ok(res),
+            (_, Some(err)) => Err(err::Error::new(
+                err::ErrorKind::ErrInternal,
+                format!("Storage failed: {}", err),
+            )
+            .into()),
+        }
+    }
+
+    // For work nobody waits on, like judging: failures are only logged
+    pub(crate) fn write_logged<R>(&self, f: impl FnOnce(&mut Db) -> R) -> R {
+        self.apply(f).0
+    }
+
+    // write for changes that may be refused
+    pub fn try_write<R>(
+        &self,
+        f: impl FnOnce(&mut Db) -> actix_web::Result<R>,
+    ) -> actix_web::Result<R> {
+        self.write(f)?
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{
+    auth::Setter,
+    conf::{Conf, Problem, Statement},
+    data::{self, internal},
+    err,
+    err::raise_err,
+    problem::{samples, Sample},
+    state::AppState,
+};
+use actix_web::{delete, get, put, web, HttpResponse, Responder, Result};
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+// Images of the problem, filled by imports and PUT /problems/{id}/statement/{file}
+pub fn statement_dir(conf: &Conf, id: i32) -> PathBuf {
+    data::problem_dir(conf, id).join("statement")
+}
+
+#[derive(Serialize)]
+struct StatementView {
+    id: i32,
+    name: String,
+    #[serde(flatten)]
+    statement: Statement,
+    samples: Vec<Sample>,
+    images: Vec<String>,
+}
+
+fn images(conf: &Conf, id: i32) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(statement_dir(conf, id)) else {
+        return vec![];
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.ends_with(".md"))
+        .collect();
+    names.sort();
+    names
+}
+
+fn view(conf: &Conf, prob: &Problem) -> Result<StatementView> {
+    let Some(statement) = &prob.statement else {
+        raise_err!(
+            err::ErrorKind::ErrNotFound,
+            "Problem {} has no statement.",
+            prob.id
+        )
+    };
+    Ok(StatementView {
+        id: prob.id,
+        name: prob.name.clone(),
+        statement: statement.clone(),
+        samples: samples(prob),
+        images: images(conf, prob.id),
+    })
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    html::push_html(&mut out, std::iter::once(Event::Text(text.into())));
+    out
+}
+
+fn safe_url(url: &str) -> bool {
+    match url.split_once(':') {
+        Some((scheme, _)) if !scheme.contains('/') => {
+            ["http", "https", "mailto"].contains(&scheme.to_ascii_lowercase().as_str())
+        }
+        _ => true,
+    }
+}
+
+// Statements are shown to everyone on the server's own origin, so raw HTML is
+// escaped and links are kept to plain web addresses. Math is kept in "math"
+// spans for KaTeX or MathJax on the client. Images named relative to the
+// statement are pointed at the image endpoint.
+fn markdown(text: &str, id: i32) -> String {
+    let relative = |url: &str| !url.contains(':') && !url.starts_with('/');
+    let parser = Parser::new_ext(text, Options::ENABLE_MATH | Options::ENABLE_TABLES).map(
+        |event| match event {
+            Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id: ref_id,
+            }) => Event::Start(Tag::Image {
+                link_type,
+                dest_url: match &dest_url {
+                    url if relative(url) => format!("/problems/{}/statement/{}", id, url).into(),
+                    url if safe_url(url) => dest_url,
+                    _ => CowStr::from(""),
+                },
+                title,
+                id: ref_id,
+            }),
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id: ref_id,
+            }) => Event::Start(Tag::Link {
+                link_type,
+                dest_url: match safe_url(&dest_url) {
+                    true => dest_url,
+                    false => CowStr::from("#"),
+                },
+                title,
+                id: ref_id,
+            }),
+            event => event,
+        },
+    );
+    let mut out = String::new();
+    html::push_html(&mut out, parser);
+    out
+}
+
+fn render(view: &StatementView) -> String {
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n",
+        escape(&view.name)
+    );
+    let sections = [
+        ("", &view.statement.legend),
+        ("Input", &view.statement.input_format),
+        ("Output", &view.statement.output_format),
+    ];
+    for (title, text) in sections {
+        if !title.is_empty() {
+            page += &format!("<h2>{}</h2>\n", title);
+        }
+        page += &markdown(text, view.id);
+    }
+    if !view.samples.is_empty() {
+        page += "<h2>Samples</h2>\n";
+    }
+    for sample in view.samples.iter() {
+        page += &format!(
+            "<h3>Sample {}</h3>\n<pre class=\"input\">{}</pre>\n<pre class=\"answer\">{}</pre>\n",
+            sample.id,
+            escape(&sample.input),
+            escape(&sample.answer)
+        );
+    }
+    if !view.statement.notes.is_empty() {
+        page += "<h2>Notes</h2>\n";
+        page += &markdown(&view.statement.notes, view.id);
+    }
+    page + "</body>\n</html>\n"
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Json,
+    Html,
+}
+
+#[derive(Deserialize)]
+pub struct StatementQuery {
+    #[serde(default)]
+    format: Format,
+}
+
+#[get("/problems/{id}/statement")]
+pub async fn get_statement(
+    id: web::Path<i32>,
+    query: web::Query<StatementQuery>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let prob = state.read(|db| db.check_prob_and_get(*id).cloned())?;
+    let view = view(&state.conf, &prob)?;
+    Ok(match query.format {
+        Format::Json => HttpResponse::Ok().json(view),
+        Format::Html => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(render(&view)),
+    })
+}
+
+#[put("/problems/{id}/statement")]
+pub async fn put_statement(
+    _: Setter,
+    id: web::Path<i32>,
+    statement: web::Json<Statement>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let prob = state.try_write(|db| {
+        let mut prob = db.check_prob_and_get(*id)?.clone();
+        prob.statement = Some(statement.into_inner());
+        db.put_problem(prob.clone());
+        Ok(prob)
+    })?;
+    Ok(web::Json(view(&state.conf, &prob)?))
+}
+
+// Plain names only, nothing outside the statement directory
+fn image_path(conf: &Conf, id: i32, file: &str) -> Result<PathBuf> {
+    let plain = file
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if !plain || file.starts_with('.') || file.ends_with(".md") {
+        raise_err!(
+            err::ErrorKind::ErrInvalidArgument,
+            "Bad file name {}.",
+            file
+        )
+    }
+    Ok(statement_dir(conf, id).join(file))
+}
+
+fn content_type(file: &str) -> &'static str {
+    let ext = file
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+#[get("/problems/{id}/statement/{file}")]
+pub async fn get_image(
+    path: web::Path<(i32, String)>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let (id, file) = path.into_inner();
+    state.read(|db| db.check_prob_and_get(id).map(|_| ()))?;
+    let Ok(content) = fs::read(image_path(&state.conf, id, &file)?) else {
+        raise_err!(err::ErrorKind::ErrNotFound, "Image {} not found.", file)
+    };
+    // Nothing uploaded may run as a page of this origin: SVGs and unknown
+    // files are downloads, and none of them may load or run anything
+    let content_type = content_type(&file);
+    let mut resp = HttpResponse::Ok();
+    resp.content_type(content_type)
+        .insert_header(("Content-Security-Policy", "default-src 'none'; sandbox"))
+        .insert_header(("X-Content-Type-Options", "nosniff"));
+    if matches!(content_type, "image/svg+xml" | "application/octet-stream") {
+        resp.insert_header(("Content-Disposition", "attachment"));
+    }
+    Ok(resp.body(content))
+}
+
+#[put("/problems/{id}/statement/{file}")]
+pub async fn put_image(
+    _: Setter,
+    path: web::Path<(i32, String)>,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let (id, file) = path.into_inner();
+    state.read(|db| db.check_prob_and_get(id).map(|_| ()))?;
+    let path = image_path(&state.conf, id, &file)?;
+    fs::create_dir_all(statement_dir(&state.conf, id)).map_err(internal)?;
+    fs::write(path, &body).map_err(internal)?;
+    Ok(web::Json(images(&state.conf, id)))
+}
+
+#[delete("/problems/{id}/statement/{file}")]
+pub async fn delete_image(
+    _: Setter,
+    path: web::Path<(i32, String)>,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let (id, file) = path.into_inner();
+    state.read(|db| db.check_prob_and_get(id).map(|_| ()))?;
+    if fs::remove_file(image_path(&state.conf, id, &file)?).is_err() {
+        raise_err!(err::ErrorKind::ErrNotFound, "Image {} not found.", file)
+    }
+    Ok(web::Json(images(&state.conf, id)))
+}
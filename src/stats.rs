@@ -0,0 +1,210 @@
+use crate::{
+    auth::{Setter, Viewer},
+    db::{visible, User},
+    err,
+    err::raise_err,
+    judge::{CaseResult, State},
+    ranklist::{standings, RankRule},
+    state::AppState,
+};
+use actix_web::{get, web, Responder, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+#[derive(Serialize)]
+struct ContestRecord {
+    contest_id: i32,
+    name: String,
+    // Under the default rule, as the viewer would see the ranklist
+    rank: i32,
+    participants: usize,
+}
+
+#[derive(Serialize)]
+struct UserStats {
+    user: User,
+    submissions: usize,
+    solved: BTreeSet<i32>,
+    attempted: BTreeSet<i32>,
+    verdicts: BTreeMap<CaseResult, usize>,
+    languages: BTreeMap<String, usize>,
+    // Submissions per UTC day, as YYYY-MM-DD
+    heatmap: BTreeMap<String, usize>,
+    contests: Vec<ContestRecord>,
+}
+
+// Everything is counted from the jobs the viewer could list with GET /jobs
+#[get("/users/{id}/stats")]
+pub async fn get_user_stats(
+    id: web::Path<i32>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    state.read(|db| {
+        let user = match db.user(id) {
+            Some(user) => user.clone(),
+            None => raise_err!(err::ErrorKind::ErrNotFound, "User {} not found.", id),
+        };
+        let mut stats = UserStats {
+            user,
+            submissions: 0,
+            solved: BTreeSet::new(),
+            attempted: BTreeSet::new(),
+            verdicts: BTreeMap::new(),
+            languages: BTreeMap::new(),
+            heatmap: BTreeMap::new(),
+            contests: vec![],
+        };
+        for contest in db.contests.iter() {
+            for job in db.jobs_of(contest.id, id) {
+                let job = visible(db, job, &viewer);
+                let sub = &job.submission;
+                stats.submissions += 1;
+                stats.attempted.insert(sub.problem_id);
+                *stats.languages.entry(sub.language.clone()).or_default() += 1;
+                let day = job.created_time.format("%Y-%m-%d").to_string();
+                *stats.heatmap.entry(day).or_default() += 1;
+                if job.state == State::Finished {
+                    *stats.verdicts.entry(job.result).or_default() += 1;
+                    if job.result == CaseResult::Accepted {
+                        stats.solved.insert(sub.problem_id);
+                    }
+                }
+            }
+            if contest.id == 0 || !contest.shown_to(db, &viewer) {
+                continue;
+            }
+            if let Some(entrant) = db.entrant_of(contest, id) {
+                let (rows, _) = standings(db, contest.id, &RankRule::default(), viewer.admin())?;
+                if let Some(row) = rows.iter().find(|row| row.id() == entrant) {
+                    stats.contests.push(ContestRecord {
+                        contest_id: contest.id,
+                        name: contest.name.clone(),
+                        rank: row.rank(),
+                        participants: rows.len(),
+                    });
+                }
+            }
+        }
+        Ok(web::Json(stats))
+    })
+}
+
+#[derive(Serialize)]
+struct CaseStats {
+    id: i32,
+    judged: usize,
+    failed: usize,
+}
+
+#[derive(Serialize)]
+struct Fastest {
+    job_id: i32,
+    user_id: i32,
+    // Sum over all cases, in microseconds
+    time: u64,
+}
+
+#[derive(Serialize)]
+struct ProblemStats {
+    problem_id: i32,
+    name: String,
+    submissions: usize,
+    accepted_users: usize,
+    // Accepted jobs out of finished ones
+    acceptance_rate: f64,
+    verdicts: BTreeMap<CaseResult, usize>,
+    cases: Vec<CaseStats>,
+    // Best accepted job of each user, the fastest few per language
+    fastest: BTreeMap<String, Vec<Fastest>>,
+}
+
+const FASTEST_PER_LANGUAGE: usize = 5;
+
+#[get("/problems/{id}/stats")]
+pub async fn get_problem_stats(
+    id: web::Path<i32>,
+    _: Setter,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    let prob = state.read(|db| db.check_prob_and_get(id).cloned())?;
+    let stats = state.read(|db| {
+        let mut stats = ProblemStats {
+            problem_id: id,
+            name: prob.name.clone(),
+            submissions: 0,
+            accepted_users: 0,
+            acceptance_rate: 0.0,
+            verdicts: BTreeMap::new(),
+            cases: (1..=prob.cases.len() as i32)
+                .map(|id| CaseStats {
+                    id,
+                    judged: 0,
+                    failed: 0,
+                })
+                .collect(),
+            fastest: BTreeMap::new(),
+        };
+        let (mut finished, mut accepted) = (0, 0);
+        let mut best: HashMap<(String, i32), Fastest> = HashMap::new();
+        for job in db
+            .jobs
+            .values()
+            .filter(|job| job.submission.problem_id == id)
+        {
+            stats.submissions += 1;
+            if job.state != State::Finished {
+                continue;
+            }
+            finished += 1;
+            *stats.verdicts.entry(job.result).or_default() += 1;
+            // Case 0 is the compilation
+            for case in job.cases.iter().skip(1) {
+                let Some(entry) = stats.cases.get_mut(case.id as usize - 1) else {
+                    continue;
+                };
+                match case.result {
+                    CaseResult::Skipped | CaseResult::Waiting | CaseResult::Running => {}
+                    CaseResult::Accepted => entry.judged += 1,
+                    _ => (entry.judged, entry.failed) = (entry.judged + 1, entry.failed + 1),
+                }
+            }
+            if job.result != CaseResult::Accepted {
+                continue;
+            }
+            accepted += 1;
+            let sub = &job.submission;
+            let time = job.cases.iter().skip(1).map(|case| case.time).sum();
+            let key = (sub.language.clone(), sub.user_id);
+            if best.get(&key).is_none_or(|cur| time < cur.time) {
+                best.insert(
+                    key,
+                    Fastest {
+                        job_id: job.id,
+                        user_id: sub.user_id,
+                        time,
+                    },
+                );
+            }
+        }
+        if finished > 0 {
+            stats.acceptance_rate = accepted as f64 / finished as f64;
+        }
+        stats.accepted_users = best
+            .keys()
+            .map(|(_, user)| user)
+            .collect::<BTreeSet<_>>()
+            .len();
+        for ((language, _), fastest) in best {
+            stats.fastest.entry(language).or_default().push(fastest);
+        }
+        for list in stats.fastest.values_mut() {
+            list.sort_by_key(|f| (f.time, f.job_id));
+            list.truncate(FASTEST_PER_LANGUAGE);
+        }
+        stats
+    });
+    Ok(web::Json(stats))
+}
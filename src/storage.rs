@@ -0,0 +1,221 @@
+use crate::{
+    auth::Credential,
+    conf::{Problem, StorageConf},
+    db::{Contest, PostJobRes, User},
+    team::Team,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub trait Record: Serialize + DeserializeOwned {
+    fn id(&self) -> i32;
+}
+
+#[derive(Default)]
+pub struct Snapshot {
+    pub jobs: Vec<PostJobRes>,
+    pub users: Vec<User>,
+    pub contests: Vec<Contest>,
+    pub teams: Vec<Team>,
+    pub problems: Vec<Problem>,
+    pub credentials: Vec<Credential>,
+}
+
+// Live data stays in memory, every change is handed to the storage right after it is applied
+pub trait Storage: Send + Sync {
+    fn load(&self) -> io::Result<Snapshot>;
+    fn save_job(&self, job: &PostJobRes) -> io::Result<()>;
+    fn save_user(&self, user: &User) -> io::Result<()>;
+    fn save_contest(&self, contest: &Contest) -> io::Result<()>;
+    fn save_team(&self, team: &Team) -> io::Result<()>;
+    fn save_problem(&self, problem: &Problem) -> io::Result<()>;
+    fn remove_problem(&self, id: i32) -> io::Result<()>;
+    fn save_credential(&self, cred: &Credential) -> io::Result<()>;
+    // Wipe everything saved so far
+    fn flush(&self) -> io::Result<()>;
+}
+
+pub fn open(conf: &StorageConf) -> io::Result<Box<dyn Storage>> {
+    Ok(match conf {
+        StorageConf::Memory => Box::new(MemoryStorage),
+        StorageConf::File { path } => Box::new(FileStorage::new(PathBuf::from(path))?),
+    })
+}
+
+// Nothing survives a restart
+pub struct MemoryStorage;
+
+impl Storage for MemoryStorage {
+    fn load(&self) -> io::Result<Snapshot> {
+        Ok(Snapshot::default())
+    }
+    fn save_job(&self, _job: &PostJobRes) -> io::Result<()> {
+        Ok(())
+    }
+    fn save_user(&self, _user: &User) -> io::Result<()> {
+        Ok(())
+    }
+    fn save_contest(&self, _contest: &Contest) -> io::Result<()> {
+        Ok(())
+    }
+    fn save_team(&self, _team: &Team) -> io::Result<()> {
+        Ok(())
+    }
+    fn save_problem(&self, _problem: &Problem) -> io::Result<()> {
+        Ok(())
+    }
+    fn remove_problem(&self, _id: i32) -> io::Result<()> {
+        Ok(())
+    }
+    fn save_credential(&self, _cred: &Credential) -> io::Result<()> {
+        Ok(())
+    }
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// One JSON-lines file per table. Every save appends the whole record,
+// the last line of an id wins and files without torn lines are compacted on load.
+// Removing appends {"id": .., "removed": true}.
+pub struct FileStorage {
+    dir: PathBuf,
+    lock: Mutex<()>,
+}
+
+const JOBS: &str = "jobs.jsonl";
+const USERS: &str = "users.jsonl";
+const CONTESTS: &str = "contests.jsonl";
+const TEAMS: &str = "teams.jsonl";
+const PROBLEMS: &str = "problems.jsonl";
+const CREDENTIALS: &str = "credentials.jsonl";
+
+impl FileStorage {
+    pub fn new(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn append<T: Record>(&self, table: &str, record: &T) -> io::Result<()> {
+        self.append_line(table, serde_json::to_string(record)?)
+    }
+
+    fn remove(&self, table: &str, id: i32) -> io::Result<()> {
+        self.append_line(table, json!({ "id": id, "removed": true }).to_string())
+    }
+
+    fn append_line(&self, table: &str, line: String) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(table))?;
+        writeln!(file, "{}", line)?;
+        file.sync_data()
+    }
+
+    fn read<T: Record>(&self, table: &str) -> io::Result<Vec<T>> {
+        let path = self.dir.join(table);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let mut records = BTreeMap::new();
+        let mut torn = false;
+        for (lineno, line) in BufReader::new(file).lines().enumerate() {
+            let at = format!("{:?}:{}", path, lineno + 1);
+            // A crash may leave a line torn, it is skipped but kept in the file
+            let value = match serde_json::from_str::<Value>(&line?) {
+                Ok(value) => value,
+                Err(err) => {
+                    log::warn!("{}: skipped ({})", at, err);
+                    torn = true;
+                    continue;
+                }
+            };
+            if value["removed"] == true {
+                if let Some(id) = value["id"].as_i64() {
+                    records.remove(&(id as i32));
+                }
+                continue;
+            }
+            // A record that doesn't fit the schema would be lost by compacting, refuse to start
+            let record = serde_json::from_value::<T>(value).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", at, err))
+            })?;
+            records.insert(record.id(), record);
+        }
+        let records: Vec<T> = records.into_values().collect();
+        if torn {
+            // End a torn last line so the next append starts a line of its own
+            if !fs::read(&path)?.ends_with(b"\n") {
+                OpenOptions::new()
+                    .append(true)
+                    .open(&path)?
+                    .write_all(b"\n")?;
+            }
+        } else {
+            let tmp_path = path.with_extension("tmp");
+            let mut tmp = File::create(&tmp_path)?;
+            for record in records.iter() {
+                writeln!(tmp, "{}", serde_json::to_string(record)?)?;
+            }
+            tmp.sync_all()?;
+            fs::rename(tmp_path, path)?;
+        }
+        Ok(records)
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self) -> io::Result<Snapshot> {
+        Ok(Snapshot {
+            jobs: self.read(JOBS)?,
+            users: self.read(USERS)?,
+            contests: self.read(CONTESTS)?,
+            teams: self.read(TEAMS)?,
+            problems: self.read(PROBLEMS)?,
+            credentials: self.read(CREDENTIALS)?,
+        })
+    }
+    fn save_job(&self, job: &PostJobRes) -> io::Result<()> {
+        self.append(JOBS, job)
+    }
+    fn save_user(&self, user: &User) -> io::Result<()> {
+        self.append(USERS, user)
+    }
+    fn save_contest(&self, contest: &Contest) -> io::Result<()> {
+        self.append(CONTESTS, contest)
+    }
+    fn save_team(&self, team: &Team) -> io::Result<()> {
+        self.append(TEAMS, team)
+    }
+    fn save_problem(&self, problem: &Problem) -> io::Result<()> {
+        self.append(PROBLEMS, problem)
+    }
+    fn remove_problem(&self, id: i32) -> io::Result<()> {
+        self.remove(PROBLEMS, id)
+    }
+    fn save_credential(&self, cred: &Credential) -> io::Result<()> {
+        self.append(CREDENTIALS, cred)
+    }
+    fn flush(&self) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        for table in [JOBS, USERS, CONTESTS, TEAMS, PROBLEMS, CREDENTIALS] {
+            match fs::remove_file(self.dir.join(table)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
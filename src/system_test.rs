@@ -0,0 +1,134 @@
+use crate::{
+    auth::Viewer,
+    db::{set_job_state, Contest},
+    err,
+    err::raise_err,
+    judge::{CaseResult, State},
+    state::{AppState, Db},
+};
+use actix_web::{get, web, Responder, Result};
+use chrono::Utc;
+use serde::Serialize;
+
+// Ended contests with pretests whose system test has not started yet
+fn due(db: &Db) -> Vec<Contest> {
+    let now = Utc::now();
+    db.contests
+        .iter()
+        .filter(|c| c.pretests && c.system_test.is_none() && c.to <= now)
+        .cloned()
+        .collect()
+}
+
+// Requeue the last pretest-judged submission of every user (or team) on every problem,
+// once no job of the contest is still being judged
+fn pick_finals(db: &mut Db) -> Vec<i32> {
+    let mut queued = vec![];
+    for mut contest in due(db) {
+        let entrants: Vec<Vec<i32>> = db
+            .entrants(&contest)
+            .into_iter()
+            .map(|id| db.members(&contest, id))
+            .collect();
+        let busy = entrants.iter().flatten().any(|&user_id| {
+            db.jobs_of(contest.id, user_id)
+                .any(|job| job.state != State::Finished)
+        });
+        if busy {
+            continue;
+        }
+        // A team's final submission is the last one of any member
+        let mut finals = vec![];
+        for members in entrants.iter() {
+            for &problem_id in contest.problem_ids.iter() {
+                let last = members
+                    .iter()
+                    .flat_map(|&user_id| db.jobs_on(contest.id, problem_id, user_id))
+                    .filter(|job| job.pretest && job.result != CaseResult::CompilationError)
+                    .max_by_key(|job| job.id);
+                if let Some(job) = last {
+                    finals.push(job.id);
+                }
+            }
+        }
+        for &job_id in finals.iter() {
+            let mut job = db.job(job_id).unwrap().clone();
+            job.pretest = false;
+            db.put_job(job);
+            set_job_state(db, job_id, State::Queueing);
+        }
+        log::info!(
+            "contest {}: system testing {} jobs",
+            contest.id,
+            finals.len()
+        );
+        queued.extend(finals.iter());
+        contest.system_test = Some(finals);
+        db.put_contest(contest);
+    }
+    queued
+}
+
+// Called periodically by the worker watcher
+pub fn start(state: &AppState) {
+    if state.read(|db| due(db).is_empty()) {
+        return;
+    }
+    for job_id in state.write_logged(pick_finals) {
+        state.queue.push(job_id);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Phase {
+    Pretests,
+    Running,
+    Finished,
+}
+
+#[derive(Serialize)]
+struct Progress {
+    contest_id: i32,
+    phase: Phase,
+    total: usize,
+    finished: usize,
+}
+
+#[get("/contests/{contest_id}/system_test")]
+pub async fn get_system_test(
+    contest_id: web::Path<i32>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let id = contest_id.into_inner();
+    state.read(|db| {
+        let contest = match db.contest(id) {
+            Some(contest) if contest.shown_to(db, &viewer) => contest,
+            _ => raise_err!(err::ErrorKind::ErrNotFound, "Contest {} not found.", id),
+        };
+        if !contest.pretests {
+            raise_err!(
+                err::ErrorKind::ErrInvalidState,
+                "Contest {} has no pretests.",
+                id
+            );
+        }
+        let job_ids = contest.system_test.as_deref().unwrap_or_default();
+        let finished = job_ids
+            .iter()
+            .filter(|&&id| db.job(id).is_some_and(|job| job.state == State::Finished))
+            .count();
+        let phase = match &contest.system_test {
+            None => Phase::Pretests,
+            Some(_) if finished < job_ids.len() => Phase::Running,
+            Some(_) => Phase::Finished,
+        };
+        Ok(web::Json(Progress {
+            contest_id: id,
+            phase,
+            total: job_ids.len(),
+            finished,
+        }))
+    })
+}
@@ -0,0 +1,146 @@
+use crate::{auth::Viewer, db::nul_id, err, err::raise_err, state::AppState, storage::Record};
+use actix_web::{get, post, web, Responder, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+// A few users sharing one ranklist row in team contests
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Team {
+    #[serde(default = "nul_id")]
+    pub(crate) id: i32,
+    pub(crate) name: String,
+    pub(crate) user_ids: Vec<i32>,
+    // Users who may join by adding themselves to user_ids
+    #[serde(default)]
+    pub(crate) invited: Vec<i32>,
+}
+
+impl Record for Team {
+    fn id(&self) -> i32 {
+        self.id
+    }
+}
+
+#[post("/teams")]
+pub async fn post_team(
+    team: web::Json<Team>,
+    viewer: Viewer,
+    state: web::Data<AppState>,
+) -> Result<impl Responder> {
+    let team = team.into_inner();
+    let mut members = team.user_ids.clone();
+    members.sort_unstable();
+    members.dedup();
+    if members.is_empty() || members.len() != team.user_ids.len() {
+        raise_err!(
+            err::ErrorKind::ErrInvalidArgument,
+            "A team needs distinct members."
+        )
+    }
+    state.try_write(|db| {
+        let mut users = team.user_ids.iter().chain(team.invited.iter());
+        if let Some(&id) = users.find(|&&id| db.user(id).is_none()) {
+            raise_err!(err::ErrorKind::ErrNotFound, "User {} not found.", id)
+        }
+        if db
+            .teams
+            .iter()
+            .any(|cur| cur.name == team.name && cur.id != team.id)
+        {
+            raise_err!(
+                err::ErrorKind::ErrInvalidArgument,
+                "Team name '{}' already exists",
+                team.name
+            )
+        }
+        let old = match team.id {
+            id if id == nul_id() => None,
+            id => match db.team(id) {
+                Some(old) => Some(old.clone()),
+                None => raise_err!(err::ErrorKind::ErrNotFound, "Team {} not found.", id),
+            },
+        };
+        let mut old_members = old.as_ref().map_or(vec![], |old| old.user_ids.clone());
+        old_members.sort_unstable();
+        // Nobody is put in a team without consent: founders start alone, members
+        // invite others, and the invited join by adding themselves. Admins may
+        // set the members directly.
+        let team = match (viewer.admin(), viewer.user_id, &old) {
+            (true, _, _) => team,
+            (false, Some(me), None) if members == [me] => team,
+            (false, Some(me), Some(old)) if old.user_ids.contains(&me) => {
+                if members.iter().any(|id| !old_members.contains(id)) {
+                    raise_err!(
+                        err::ErrorKind::ErrForbidden,
+                        "Invite users instead of adding them."
+                    )
+                }
+                team
+            }
+            // Accepting an invitation only adds the invited user
+            (false, Some(me), Some(old))
+                if old.invited.contains(&me)
+                    && members.len() == old_members.len() + 1
+                    && members.contains(&me)
+                    && old_members.iter().all(|id| members.contains(id)) =>
+            {
+                Team {
+                    user_ids: team.user_ids,
+                    ..old.clone()
+                }
+            }
+            _ => raise_err!(
+                err::ErrorKind::ErrForbidden,
+                "Only members and invited users may change the team."
+            ),
+        };
+        // A user may only be in one team per contest, checked on registration.
+        // Members are locked from then on until the contest is over.
+        if old_members != members {
+            let now = Utc::now();
+            let registered = db
+                .contests
+                .iter()
+                .find(|c| c.team_contest && c.to > now && c.team_ids.contains(&team.id));
+            if let Some(contest) = registered {
+                raise_err!(
+                    err::ErrorKind::ErrInvalidState,
+                    "Team {} is registered in contest {}.",
+                    team.id,
+                    contest.id
+                )
+            }
+        }
+        let invited = team
+            .invited
+            .iter()
+            .filter(|id| !team.user_ids.contains(id))
+            .copied()
+            .collect();
+        let team = Team { invited, ..team };
+        let team = match team.id {
+            id if id == nul_id() => Team {
+                id: db.teams.len() as i32,
+                ..team
+            },
+            _ => team,
+        };
+        db.put_team(team.clone());
+        Ok(web::Json(team))
+    })
+}
+
+#[get("/teams")]
+pub async fn get_teams(state: web::Data<AppState>) -> Result<impl Responder> {
+    let teams: Vec<Team> = state.read(|db| db.teams.clone());
+    Ok(web::Json(teams))
+}
+
+#[get("/teams/{id}")]
+pub async fn get_team(id: web::Path<i32>, state: web::Data<AppState>) -> Result<impl Responder> {
+    let id = id.into_inner();
+    match state.read(|db| db.team(id).cloned()) {
+        Some(team) => Ok(web::Json(team)),
+        None => raise_err!(err::ErrorKind::ErrNotFound, "Team {} not found.", id),
+    }
+}
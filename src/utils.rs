@@ -1,5 +1,7 @@
+use std::cmp::Ordering;
 use std::fs;
 
+#[allow(dead_code)]
 pub fn file2str(file: &str) -> std::io::Result<String> {
     fs::read_to_string(file)
 }
@@ -9,3 +11,80 @@ pub fn apmax<T: Clone + PartialOrd>(a: &mut T, b: T) {
         *a = b;
     }
 }
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Runs of digits compare as numbers, so "2.in" < "10.in"
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    // Digits are single bytes, so every split is on a char boundary
+    fn runs(s: &str) -> Vec<&str> {
+        let bytes = s.as_bytes();
+        let mut res = vec![];
+        let mut start = 0;
+        for i in 1..=bytes.len() {
+            if i == bytes.len() || bytes[i].is_ascii_digit() != bytes[i - 1].is_ascii_digit() {
+                res.push(&s[start..i]);
+                start = i;
+            }
+        }
+        res
+    }
+    let digits = |s: &str| s.starts_with(|c: char| c.is_ascii_digit());
+    for (x, y) in runs(a).into_iter().zip(runs(b)) {
+        let ord = match digits(x) && digits(y) {
+            true => {
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            false => x.cmp(y),
+        };
+        if ord.is_ne() {
+            return ord;
+        }
+    }
+    a.cmp(b)
+}
+
+// RFC 3339 in UTC with milliseconds, e.g. 2022-08-27T02:05:29.000Z
+pub mod rfc3339 {
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn parse(s: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+        DateTime::parse_from_rfc3339(s).map(|t| t.with_timezone(&Utc))
+    }
+
+    pub fn serialize<S: Serializer>(t: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&t.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<DateTime<Utc>, D::Error> {
+        let s = String::deserialize(d)?;
+        parse(&s).map_err(|e| de::Error::custom(format!("bad time {:?}: {}", s, e)))
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            t: &Option<DateTime<Utc>>,
+            s: S,
+        ) -> Result<S::Ok, S::Error> {
+            match t {
+                Some(t) => super::serialize(t, s),
+                None => s.serialize_none(),
+            }
+        }
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            d: D,
+        ) -> Result<Option<DateTime<Utc>>, D::Error> {
+            match Option::<String>::deserialize(d)? {
+                Some(s) => parse(&s)
+                    .map(Some)
+                    .map_err(|e| de::Error::custom(format!("bad time {:?}: {}", s, e))),
+                None => Ok(None),
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+mod support;
+
+use actix_web::{test, App};
+use oj::state::AppState;
+use serde_json::{json, Value};
+use support::{conf_with, new_open_state, submission, submit};
+
+#[actix_web::test]
+async fn test_instances_are_isolated() {
+    let first = new_open_state();
+    let second = new_open_state();
+    let app1 = test::init_service(App::new().app_data(first).configure(oj::routes)).await;
+    let app2 = test::init_service(App::new().app_data(second).configure(oj::routes)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(json!({ "name": "alice" }))
+        .to_request();
+    let user: Value = test::call_and_read_body_json(&app1, req).await;
+    assert_eq!(
+        user,
+        json!({ "id": 1, "name": "alice", "role": "contestant" })
+    );
+
+    let req = test::TestRequest::get().uri("/users").to_request();
+    let users: Value = test::call_and_read_body_json(&app1, req).await;
+    assert_eq!(users.as_array().unwrap().len(), 2);
+    let req = test::TestRequest::get().uri("/users").to_request();
+    let users: Value = test::call_and_read_body_json(&app2, req).await;
+    assert_eq!(users, json!([{ "id": 0, "name": "root", "role": "admin" }]));
+}
+
+#[actix_web::test]
+async fn test_jobs_pagination() {
+    let app = test::init_service(App::new().app_data(new_open_state()).configure(oj::routes)).await;
+    for i in 0..5 {
+        let mut job = submission(0, 0, 0);
+        job["source_code"] = json!(format!("// {}", i));
+        assert!(submit!(app, job).status().is_success());
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/jobs?page=2&per_page=2&order=desc&brief=true")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.headers().get("X-Total-Count").unwrap(), "5");
+    assert_eq!(res.headers().get("X-Total-Pages").unwrap(), "3");
+    let jobs: Value = test::read_body_json(res).await;
+    let ids: Vec<_> = jobs
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|j| j["id"].clone())
+        .collect();
+    assert_eq!(ids, vec![json!(2), json!(1)]);
+    assert!(jobs[0].get("cases").is_none());
+    assert!(jobs[0]["submission"].get("source_code").is_none());
+
+    let req = test::TestRequest::get()
+        .uri("/jobs?sort_by=color")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), 400);
+    let err: Value = test::read_body_json(res).await;
+    assert_eq!(err["reason"], "ERR_INVALID_ARGUMENT");
+    let req = test::TestRequest::get()
+        .uri("/jobs?per_page=0")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_time_filters() {
+    let app = test::init_service(App::new().app_data(new_open_state()).configure(oj::routes)).await;
+    let job: Value = test::read_body_json(submit!(app, submission(0, 0, 0))).await;
+    let created = job["created_time"].as_str().unwrap();
+    assert_eq!(created.len(), "2022-08-27T02:05:29.000Z".len());
+    assert!(created.ends_with('Z'));
+
+    // Same instant as 2000-01-01T00:00:00.000Z, written differently
+    let req = test::TestRequest::get()
+        .uri("/jobs?from=2000-01-01T08:00:00%2B08:00")
+        .to_request();
+    let jobs: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(jobs.as_array().unwrap().len(), 1);
+    let req = test::TestRequest::get()
+        .uri("/jobs?to=2000-01-01T00:00:00.000Z")
+        .to_request();
+    let jobs: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(jobs.as_array().unwrap().len(), 0);
+
+    let req = test::TestRequest::get()
+        .uri("/jobs?from=yesterday")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), 400);
+    let err: Value = test::read_body_json(res).await;
+    assert_eq!(err["reason"], "ERR_INVALID_ARGUMENT");
+
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .set_json(json!({
+            "name": "bad",
+            "from": "2022-08-27 02:05:29 UTC",
+            "to": "2030-01-01T00:00:00.000Z",
+            "problem_ids": [0],
+            "user_ids": [0],
+            "submission_limit": 1
+        }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), 400);
+    let err: Value = test::read_body_json(res).await;
+    assert_eq!(err["reason"], "ERR_INVALID_ARGUMENT");
+}
+
+#[actix_web::test]
+async fn test_storage_load() {
+    let dir = tempdir::TempDir::new("oj-storage").unwrap();
+    let storage = dir.path().to_str().unwrap().to_string();
+    let conf = || {
+        conf_with(|conf| {
+            conf["storage"] = json!({ "type": "file", "path": storage });
+        })
+    };
+    drop(AppState::new(conf()).unwrap());
+    let users = dir.path().join("users.jsonl");
+    let saved = std::fs::read_to_string(&users).unwrap();
+
+    // A torn line is skipped and kept
+    let torn = format!("{}{{\"id\": 1, \"na", saved);
+    std::fs::write(&users, &torn).unwrap();
+    drop(AppState::new(conf()).unwrap());
+    assert_eq!(std::fs::read_to_string(&users).unwrap(), torn + "\n");
+
+    // A record that doesn't fit fails the load and stays on disk
+    let bad = format!("{}{{\"id\": 1, \"name\": 5}}\n", saved);
+    std::fs::write(&users, &bad).unwrap();
+    assert!(AppState::new(conf()).is_err());
+    assert_eq!(std::fs::read_to_string(&users).unwrap(), bad);
+
+    // So does a gap in the ids of users
+    let gap = format!("{}{{\"id\": 2, \"name\": \"bob\"}}\n", saved);
+    std::fs::write(&users, &gap).unwrap();
+    assert!(AppState::new(conf()).is_err());
+    std::fs::write(
+        &users,
+        format!("{}{{\"id\": 1, \"name\": \"bob\"}}\n", saved),
+    )
+    .unwrap();
+    let state = AppState::new(conf()).unwrap();
+    assert!(state.read(|db| db.user(1).is_some()));
+}
+
+#[actix_web::test]
+async fn test_storage_failure() {
+    let dir = tempdir::TempDir::new("oj-storage").unwrap();
+    let storage = dir.path().to_str().unwrap().to_string();
+    let state = support::new_state_with(|conf| {
+        conf["server"]["open"] = json!(true);
+        conf["storage"] = json!({ "type": "file", "path": storage });
+    });
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+
+    // Nothing can be appended once the table is a directory
+    let users = dir.path().join("users.jsonl");
+    std::fs::remove_file(&users).unwrap();
+    std::fs::create_dir(&users).unwrap();
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(json!({ "name": "alice" }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), 500);
+    let err: Value = test::read_body_json(res).await;
+    assert_eq!(err["reason"], "ERR_INTERNAL");
+}
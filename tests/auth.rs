@@ -0,0 +1,294 @@
+mod support;
+
+use actix_web::{test, App};
+use serde_json::{json, Value};
+use support::{login, new_open_state, new_state_with, submission, submit, time};
+
+#[actix_web::test]
+async fn test_auth() {
+    let state = new_state_with(|conf| {
+        conf["server"]["admin_token"] = json!("secret");
+        conf["server"]["require_auth"] = json!(true);
+    });
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(json!({ "name": "alice" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let mut tokens = vec![];
+    for name in ["alice", "bob"] {
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "name": name, "password": "pw" }))
+            .to_request();
+        let user: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(user["name"], name);
+        assert!(user.get("password").is_none());
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .set_json(json!({ "name": name, "password": "wrong" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .set_json(json!({ "name": name, "password": "pw" }))
+            .to_request();
+        let session: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(session["user"], user);
+        tokens.push(format!("Bearer {}", session["token"].as_str().unwrap()));
+    }
+
+    let mut job = submission(0, 0, 0);
+    job["source_code"] = json!("fn main() {}");
+    assert_eq!(submit!(app, job).status(), 401);
+    let res: Value =
+        test::read_body_json(submit!(app, job, ("Authorization", tokens[0].as_str()))).await;
+    assert_eq!(res["submission"]["user_id"], 1);
+
+    // Only the owner and admins see the source
+    for (token, source) in [
+        (tokens[0].as_str(), "fn main() {}"),
+        (tokens[1].as_str(), ""),
+        ("Bearer secret", "fn main() {}"),
+    ] {
+        let req = test::TestRequest::get()
+            .uri("/jobs/0")
+            .insert_header(("Authorization", token))
+            .to_request();
+        let res: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["submission"]["source_code"], source);
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .insert_header(("Authorization", tokens[1].as_str()))
+        .set_json(json!({ "id": 1, "name": "mallory" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::post()
+        .uri("/logout")
+        .insert_header(("Authorization", tokens[0].as_str()))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    assert_eq!(
+        submit!(app, job, ("Authorization", tokens[0].as_str())).status(),
+        401
+    );
+}
+
+#[actix_web::test]
+async fn test_roles() {
+    let state = new_state_with(|conf| {
+        conf["server"]["require_auth"] = json!(true);
+        conf["admin"] = json!({ "name": "boss", "password": "boss" });
+    });
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let mut tokens = vec![];
+    for (name, password) in [("boss", "boss"), ("alice", "pw")] {
+        if name != "boss" {
+            let req = test::TestRequest::post()
+                .uri("/users")
+                .set_json(json!({ "name": name, "password": password }))
+                .to_request();
+            assert!(test::call_service(&app, req).await.status().is_success());
+        }
+        tokens.push(login!(app, name, password));
+    }
+    let boss = ("Authorization", tokens[0].as_str());
+    let alice = ("Authorization", tokens[1].as_str());
+
+    let contest = json!({
+        "name": "round",
+        "from": time(-60),
+        "to": time(60),
+        "problem_ids": [0],
+        "user_ids": [0, 1],
+        "submission_limit": 10
+    });
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .insert_header(alice)
+        .set_json(&contest)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::post()
+        .uri("/rejudge")
+        .insert_header(alice)
+        .set_json(json!({}))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .insert_header(alice)
+        .set_json(json!({ "id": 1, "name": "alice", "role": "admin" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .insert_header(boss)
+        .set_json(&contest)
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .insert_header(boss)
+        .set_json(json!({ "id": 1, "name": "alice", "role": "problem_setter" }))
+        .to_request();
+    let user: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(user["role"], "problem_setter");
+    // Renaming keeps the role
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .insert_header(alice)
+        .set_json(json!({ "id": 1, "name": "alicia" }))
+        .to_request();
+    let user: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        user,
+        json!({ "id": 1, "name": "alicia", "role": "problem_setter" })
+    );
+    // Names stay unique, they are what users log in with
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .insert_header(alice)
+        .set_json(json!({ "id": 1, "name": "boss" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::post()
+        .uri("/login")
+        .set_json(json!({ "name": "alicia", "password": "pw" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let mut job = submission(1, 1, 0);
+    job["source_code"] = json!("fn main() {}");
+    assert!(submit!(app, job, alice).status().is_success());
+    let req = test::TestRequest::get()
+        .uri("/jobs")
+        .insert_header(boss)
+        .to_request();
+    let jobs: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(jobs[0]["submission"]["source_code"], "fn main() {}");
+}
+
+#[actix_web::test]
+async fn test_open_mode_tokens() {
+    let app = test::init_service(App::new().app_data(new_open_state()).configure(oj::routes)).await;
+    for name in ["alice", "bob"] {
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "name": name, "password": "pw" }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    let alice = login!(app, "alice", "pw");
+    let alice = ("Authorization", alice.as_str());
+
+    // No credentials at all: admin, as the server is configured open
+    let mut job = submission(2, 0, 0);
+    job["source_code"] = json!("secret code");
+    assert!(submit!(app, job).status().is_success());
+    let req = test::TestRequest::get().uri("/jobs/0").to_request();
+    let job: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(job["submission"]["source_code"], "secret code");
+
+    // A contestant's token is a contestant
+    let req = test::TestRequest::get()
+        .uri("/jobs/0")
+        .insert_header(alice)
+        .to_request();
+    let job: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(job["submission"]["source_code"], "");
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .insert_header(alice)
+        .set_json(json!({ "id": 2, "name": "bob", "role": "admin" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .insert_header(alice)
+        .set_json(json!({ "id": 1, "name": "alice", "role": "admin" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    // So is an unknown token
+    let req = test::TestRequest::get()
+        .uri("/jobs/0")
+        .insert_header(("Authorization", "Bearer nobody"))
+        .to_request();
+    let job: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(job["submission"]["source_code"], "");
+}
+
+#[actix_web::test]
+async fn test_closed_by_default() {
+    let state = new_state_with(|conf| conf["judge"] = json!({ "local_workers": 0 }));
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .set_json(json!({
+            "name": "round",
+            "from": time(-60),
+            "to": time(60),
+            "problem_ids": [0],
+            "user_ids": [0],
+            "submission_limit": 10
+        }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::post()
+        .uri("/internal/workers/test/fetch")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    // Dropping the token doesn't make a contestant admin
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(json!({ "name": "alice", "password": "pw" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    // Nor can anyone submit in alice's name without logging in
+    assert_eq!(submit!(app, submission(1, 0, 0)).status(), 401);
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(json!({ "id": 1, "name": "alice", "role": "admin" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+}
+
+#[actix_web::test]
+async fn test_worker_token() {
+    let state = new_state_with(|conf| conf["server"]["admin_token"] = json!("secret"));
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let fetch = |auth: Option<&str>| {
+        let req = test::TestRequest::post().uri("/internal/workers/evil/fetch");
+        match auth {
+            Some(auth) => req.insert_header(("Authorization", auth)),
+            None => req,
+        }
+        .to_request()
+    };
+    assert_eq!(test::call_service(&app, fetch(None)).await.status(), 403);
+    // The admin token is not a worker token
+    let req = fetch(Some("Bearer secret"));
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = fetch(Some("Bearer worker"));
+    assert_eq!(test::call_service(&app, req).await.status(), 204);
+    let req = test::TestRequest::post()
+        .uri("/internal/workers/evil/jobs/0/report")
+        .set_json(json!([]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    // Without a worker token only admins may fetch
+    let state = new_state_with(|conf| {
+        conf["server"]["admin_token"] = json!("secret");
+        conf["judge"] = json!({ "local_workers": 0 });
+    });
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    assert_eq!(test::call_service(&app, fetch(None)).await.status(), 403);
+    let req = fetch(Some("Bearer secret"));
+    assert_eq!(test::call_service(&app, req).await.status(), 204);
+}
@@ -1,3 +1,10 @@
+#![allow(
+    clippy::expect_fun_call,
+    clippy::needless_borrow,
+    clippy::needless_borrows_for_generic_args,
+    clippy::single_component_path_imports
+)]
+
 use assert_json_diff::{assert_json_matches_no_panic, CompareMode, Config};
 use lazy_static::lazy_static;
 use reqwest::blocking::{Client, RequestBuilder};
@@ -35,7 +42,7 @@ use std::time::Duration;
 // LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
-fn build_and_find_path(name: &str) -> PathBuf {
+pub fn build_and_find_path(name: &str) -> PathBuf {
     // This project defines some associated binaries for testing, and we shell out to them in
     // these tests. `cargo test` doesn't automatically build associated binaries, so this
     // function takes care of building them explicitly, with the right debug/release flavor.
@@ -0,0 +1,657 @@
+mod support;
+
+use actix_web::{test, App};
+use oj::judge::{judge_safe, Task};
+use serde_json::{json, Value};
+use support::{
+    judge_next, login, new_open_state, new_state_with, submission, submit, time, WORKER,
+};
+
+#[actix_web::test]
+async fn test_ranklist_etag() {
+    let app = test::init_service(App::new().app_data(new_open_state()).configure(oj::routes)).await;
+    let req = test::TestRequest::get()
+        .uri("/contests/0/ranklist?scoring_rule=highest")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), 200);
+    let etag = res.headers().get("ETag").unwrap().clone();
+
+    let req = test::TestRequest::get()
+        .uri("/contests/0/ranklist?scoring_rule=highest")
+        .insert_header(("If-None-Match", etag.clone()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 304);
+
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(json!({ "name": "alice" }))
+        .to_request();
+    test::call_service(&app, req).await;
+    let req = test::TestRequest::get()
+        .uri("/contests/0/ranklist?scoring_rule=highest")
+        .insert_header(("If-None-Match", etag.clone()))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), 200);
+    assert_ne!(res.headers().get("ETag").unwrap(), &etag);
+    let ranks: Value = test::read_body_json(res).await;
+    assert_eq!(ranks.as_array().unwrap().len(), 2);
+
+    let req = test::TestRequest::get()
+        .uri("/contests/0/ranklist?scoring_rule=lowest")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_icpc_ranklist() {
+    let app = test::init_service(App::new().app_data(new_open_state()).configure(oj::routes)).await;
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .set_json(json!({
+            "name": "icpc",
+            "from": time(-30),
+            "to": time(60),
+            "scoring_mode": "icpc",
+            "problem_ids": [0],
+            "user_ids": [0],
+            "submission_limit": 10
+        }))
+        .to_request();
+    let contest: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(contest["id"], 1);
+    for _ in 0..3 {
+        assert!(submit!(app, submission(0, 1, 0)).status().is_success());
+    }
+    // Compilation errors are not counted
+    let req = test::TestRequest::post()
+        .uri("/internal/workers/test/fetch")
+        .insert_header(WORKER)
+        .to_request();
+    let task: Value = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::post()
+        .uri(&format!(
+            "/internal/workers/test/jobs/{}/report",
+            task["job_id"]
+        ))
+        .insert_header(WORKER)
+        .set_json(json!([
+            { "id": 0, "result": "Compilation Error", "time": 0, "memory": 0, "info": "" },
+            { "id": 1, "result": "Waiting", "time": 0, "memory": 0, "info": "" }
+        ]))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    judge_next!(app, "Wrong Answer");
+    judge_next!(app, "Accepted");
+
+    let req = test::TestRequest::get()
+        .uri("/contests/1/ranklist")
+        .to_request();
+    let ranks: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ranks[0]["solved"], 1);
+    assert_eq!(ranks[0]["penalty"], 50);
+    assert_eq!(
+        ranks[0]["problems"],
+        json!([{ "problem_id": 0, "attempts": 2, "pending": 0, "solve_time": 30 }])
+    );
+}
+
+#[actix_web::test]
+async fn test_freeze_and_reveal() {
+    let state = new_state_with(|conf| conf["server"]["admin_token"] = json!("secret"));
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let admin = ("Authorization", "Bearer secret");
+    let mut contest = json!({
+        "name": "frozen",
+        "from": time(-60),
+        "to": time(60),
+        "freeze_time": time(-10),
+        "scoring_mode": "icpc",
+        "visibility": "public",
+        "problem_ids": [0],
+        "user_ids": [0],
+        "submission_limit": 10
+    });
+    // Unfreezing is up to the server, not the request
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .insert_header(admin)
+        .set_json({
+            let mut contest = contest.clone();
+            contest["unfrozen"] = json!(true);
+            contest["revealed"] = json!([[0, 0]]);
+            contest
+        })
+        .to_request();
+    let created: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(created["unfrozen"], false);
+    assert_eq!(created["revealed"], json!([]));
+    assert!(submit!(app, submission(0, 1, 0), admin)
+        .status()
+        .is_success());
+    judge_next!(app, "Accepted");
+
+    let req = test::TestRequest::get()
+        .uri("/contests/1/ranklist")
+        .to_request();
+    let ranks: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ranks[0]["solved"], 0);
+    assert_eq!(ranks[0]["problems"][0]["pending"], 1);
+    let req = test::TestRequest::get()
+        .uri("/contests/1/ranklist")
+        .insert_header(admin)
+        .to_request();
+    let ranks: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ranks[0]["solved"], 1);
+    // Nor can others find the verdict anywhere else
+    let req = test::TestRequest::get()
+        .uri("/jobs?contest_id=1&result=Accepted")
+        .to_request();
+    let jobs: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(jobs, json!([]));
+    let req = test::TestRequest::get().uri("/jobs/0").to_request();
+    let job: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(job["result"], "Waiting");
+    assert_eq!(job["cases"], json!([]));
+    let req = test::TestRequest::get().uri("/users/0/stats").to_request();
+    let stats: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(stats["solved"], json!([]));
+    let req = test::TestRequest::get()
+        .uri("/jobs/0")
+        .insert_header(admin)
+        .to_request();
+    let job: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(job["result"], "Accepted");
+
+    let req = test::TestRequest::post()
+        .uri("/contests/1/reveal")
+        .insert_header(admin)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    // End the contest so the ceremony can start
+    contest["id"] = json!(1);
+    contest["to"] = json!(time(0));
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .insert_header(admin)
+        .set_json(&contest)
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let req = test::TestRequest::post()
+        .uri("/contests/1/reveal")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::post()
+        .uri("/contests/1/reveal")
+        .insert_header(admin)
+        .to_request();
+    let reveal: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(reveal["user_id"], 0);
+    assert_eq!(reveal["problem_id"], 0);
+    assert_eq!(reveal["ranklist"][0]["solved"], 1);
+    let req = test::TestRequest::post()
+        .uri("/contests/1/reveal")
+        .insert_header(admin)
+        .to_request();
+    let reveal: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(reveal["user_id"], Value::Null);
+
+    let req = test::TestRequest::get()
+        .uri("/contests/1")
+        .insert_header(admin)
+        .to_request();
+    let shown: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(shown["unfrozen"], true);
+    let req = test::TestRequest::get()
+        .uri("/contests/1/ranklist")
+        .to_request();
+    let ranks: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ranks[0]["solved"], 1);
+    let req = test::TestRequest::get().uri("/jobs/0").to_request();
+    let job: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(job["result"], "Accepted");
+
+    // Editing the contest afterwards keeps it revealed
+    contest["name"] = json!("thawed");
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .insert_header(admin)
+        .set_json(&contest)
+        .to_request();
+    let edited: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(edited["unfrozen"], true);
+    let req = test::TestRequest::get()
+        .uri("/contests/1/ranklist")
+        .to_request();
+    let ranks: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ranks[0]["solved"], 1);
+}
+
+#[actix_web::test]
+async fn test_oi_mode() {
+    let state = new_state_with(|conf| conf["server"]["admin_token"] = json!("secret"));
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let admin = ("Authorization", "Bearer secret");
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .insert_header(admin)
+        .set_json(json!({
+            "name": "oi",
+            "from": time(-60),
+            "to": time(60),
+            "oi_mode": true,
+            "visibility": "public",
+            "problem_ids": [0],
+            "user_ids": [0],
+            "submission_limit": 10
+        }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    assert!(submit!(app, submission(0, 1, 0), admin)
+        .status()
+        .is_success());
+    judge_next!(app, "Accepted");
+
+    let req = test::TestRequest::get().uri("/jobs/0").to_request();
+    let job: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(job["state"], "Finished");
+    assert_eq!(job["result"], "Compilation Success");
+    assert_eq!(job["score"], 0.0);
+    assert_eq!(job["cases"].as_array().unwrap().len(), 1);
+    let req = test::TestRequest::get()
+        .uri("/jobs?result=Accepted")
+        .to_request();
+    let jobs: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(jobs, json!([]));
+    let req = test::TestRequest::get()
+        .uri("/contests/1/ranklist")
+        .to_request();
+    let ranks: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ranks[0]["scores"], json!([0.0]));
+
+    let req = test::TestRequest::get()
+        .uri("/jobs/0")
+        .insert_header(admin)
+        .to_request();
+    let job: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(job["result"], "Accepted");
+    let req = test::TestRequest::get()
+        .uri("/contests/1/ranklist")
+        .insert_header(admin)
+        .to_request();
+    let ranks: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ranks[0]["scores"], json!([100.0]));
+}
+
+#[actix_web::test]
+async fn test_pretests_and_system_test() {
+    // A "compiler" that installs a shell script, judged on one passing and one failing case
+    let dir = tempdir::TempDir::new("oj-pretests").unwrap();
+    let file = |name: &str, content: &str| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    let case = |input, answer| json!({ "score": 50, "input_file": input, "answer_file": answer, "time_limit": 1000000, "memory_limit": 0 });
+    let cases = json!([
+        case(file("1.in", "1\n"), file("1.ans", "1\n")),
+        case(file("2.in", "2\n"), file("2.ans", "3\n"))
+    ]);
+    let state = new_state_with(|conf| {
+        conf["server"]["open"] = json!(true);
+        conf["problems"][0]["cases"] = cases;
+        conf["problems"][0]["misc"] = json!({ "pretests": [1] });
+        conf["languages"][0]["command"] = json!(["install", "-m", "755", "%INPUT%", "%OUTPUT%"]);
+    });
+    let app = test::init_service(App::new().app_data(state.clone()).configure(oj::routes)).await;
+    let mut contest = json!({
+        "name": "round",
+        "from": time(-60),
+        "to": time(60),
+        "pretests": true,
+        "problem_ids": [0],
+        "user_ids": [0],
+        "submission_limit": 10
+    });
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .set_json(&contest)
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let mut job = submission(0, 1, 0);
+    job["source_code"] = json!("#!/bin/sh\ncat\n");
+    let job: Value = test::read_body_json(submit!(app, job)).await;
+    assert_eq!(job["pretest"], true);
+
+    let judge = |task: Value| {
+        let task: Task = serde_json::from_value(task).unwrap();
+        let cases = judge_safe(
+            &task.submission,
+            &task.language,
+            &task.problem,
+            task.pretests_only,
+        );
+        (task.job_id, serde_json::to_value(cases).unwrap())
+    };
+    let req = test::TestRequest::post()
+        .uri("/internal/workers/test/fetch")
+        .insert_header(WORKER)
+        .to_request();
+    let (job_id, cases) = judge(test::call_and_read_body_json(&app, req).await);
+    assert_eq!(cases[2]["result"], "Skipped");
+    let req = test::TestRequest::post()
+        .uri(&format!("/internal/workers/test/jobs/{}/report", job_id))
+        .insert_header(WORKER)
+        .set_json(cases)
+        .to_request();
+    let job: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(job["result"], "Accepted");
+
+    let req = test::TestRequest::get()
+        .uri("/contests/1/system_test")
+        .to_request();
+    let progress: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(progress["phase"], "pretests");
+    contest["id"] = json!(1);
+    contest["to"] = json!(time(0));
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .set_json(&contest)
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    oj::system_test::start(&state);
+    let req = test::TestRequest::get()
+        .uri("/contests/1/system_test")
+        .to_request();
+    let progress: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        progress,
+        json!({ "contest_id": 1, "phase": "running", "total": 1, "finished": 0 })
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/internal/workers/test/fetch")
+        .insert_header(WORKER)
+        .to_request();
+    let (job_id, cases) = judge(test::call_and_read_body_json(&app, req).await);
+    let req = test::TestRequest::post()
+        .uri(&format!("/internal/workers/test/jobs/{}/report", job_id))
+        .insert_header(WORKER)
+        .set_json(cases)
+        .to_request();
+    let job: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(job["result"], "Wrong Answer");
+    assert_eq!(job["pretest"], false);
+    let req = test::TestRequest::get()
+        .uri("/contests/1/system_test")
+        .to_request();
+    let progress: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(progress["phase"], "finished");
+}
+
+#[actix_web::test]
+async fn test_contest_registration() {
+    let state = new_state_with(|conf| {
+        conf["server"]["admin_token"] = json!("secret");
+        conf["server"]["require_auth"] = json!(true);
+    });
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let admin = ("Authorization", "Bearer secret");
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(json!({ "name": "alice", "password": "pw" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let token = login!(app, "alice", "pw");
+    let alice = ("Authorization", token.as_str());
+
+    for (name, extra) in [
+        ("public", json!({ "visibility": "public" })),
+        (
+            "invite",
+            json!({ "visibility": "invite", "invite_code": "abc" }),
+        ),
+        ("private", json!({})),
+        (
+            "closed",
+            json!({ "visibility": "public", "registration_to": time(-30) }),
+        ),
+    ] {
+        let mut contest = json!({
+            "name": name,
+            "from": time(-60),
+            "to": time(60),
+            "problem_ids": [0],
+            "user_ids": [],
+            "submission_limit": 10
+        });
+        contest
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        let req = test::TestRequest::post()
+            .uri("/contests")
+            .insert_header(admin)
+            .set_json(&contest)
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .insert_header(admin)
+        .set_json(json!({
+            "name": "bad",
+            "from": time(-60),
+            "to": time(60),
+            "visibility": "invite",
+            "problem_ids": [0],
+            "user_ids": [],
+            "submission_limit": 10
+        }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let req = test::TestRequest::get()
+        .uri("/contests")
+        .insert_header(alice)
+        .to_request();
+    let contests: Value = test::call_and_read_body_json(&app, req).await;
+    let names: Vec<_> = contests
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["name"].clone())
+        .collect();
+    assert_eq!(names, ["public", "invite", "closed"]);
+    assert_eq!(contests[1]["invite_code"], Value::Null);
+
+    let register = |id: i32, body: Value, auth: Option<(&'static str, String)>| {
+        let mut req = test::TestRequest::post()
+            .uri(&format!("/contests/{}/register", id))
+            .set_json(body);
+        if let Some(auth) = auth {
+            req = req.insert_header(auth);
+        }
+        req.to_request()
+    };
+    let alice = Some(("Authorization", token.clone()));
+    let admin = Some(("Authorization", "Bearer secret".to_string()));
+    let req = register(1, json!({}), None);
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+    let req = register(1, json!({}), alice.clone());
+    let contest: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(contest["user_ids"], json!([1]));
+    let req = register(2, json!({ "invite_code": "xyz" }), alice.clone());
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = register(2, json!({ "invite_code": "abc" }), alice.clone());
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = register(3, json!({}), alice.clone());
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    // Nor are its standings or system test shown
+    for uri in ["/contests/3/ranklist", "/contests/3/system_test"] {
+        let req = test::TestRequest::get()
+            .uri(uri)
+            .insert_header(("Authorization", token.as_str()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+    }
+    let req = register(4, json!({}), alice.clone());
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = register(3, json!({ "user_id": 1 }), admin);
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let req = test::TestRequest::get()
+        .uri("/contests/3")
+        .insert_header(("Authorization", token.as_str()))
+        .to_request();
+    let contest: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(contest["user_ids"], json!([1]));
+    let req = test::TestRequest::get()
+        .uri("/contests/3/ranklist")
+        .insert_header(("Authorization", token.as_str()))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    assert!(
+        submit!(app, submission(1, 1, 0), ("Authorization", token.as_str()))
+            .status()
+            .is_success()
+    );
+}
+
+#[actix_web::test]
+async fn test_team_contest() {
+    let app = test::init_service(App::new().app_data(new_open_state()).configure(oj::routes)).await;
+    for name in ["alice", "bob", "carol"] {
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "name": name }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    for (name, members) in [("ab", json!([1, 2])), ("c", json!([3])), ("a", json!([1]))] {
+        let req = test::TestRequest::post()
+            .uri("/teams")
+            .set_json(json!({ "name": name, "user_ids": members }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+    let req = test::TestRequest::post()
+        .uri("/teams")
+        .set_json(json!({ "name": "ab", "user_ids": [3] }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .set_json(json!({
+            "name": "teams",
+            "from": time(-60),
+            "to": time(60),
+            "visibility": "public",
+            "team_contest": true,
+            "team_ids": [0],
+            "problem_ids": [0],
+            "user_ids": [],
+            "submission_limit": 2
+        }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let register = |team_id: i32| {
+        test::TestRequest::post()
+            .uri("/contests/1/register")
+            .set_json(json!({ "team_id": team_id }))
+            .to_request()
+    };
+    let contest: Value = test::call_and_read_body_json(&app, register(1)).await;
+    assert_eq!(contest["team_ids"], json!([0, 1]));
+    assert_eq!(test::call_service(&app, register(2)).await.status(), 400);
+
+    assert!(submit!(app, submission(2, 1, 0)).status().is_success());
+    judge_next!(app, "Accepted");
+    assert!(submit!(app, submission(1, 1, 0)).status().is_success());
+    judge_next!(app, "Wrong Answer");
+    // The limit counts every member of the team
+    let res: Value = test::read_body_json(submit!(app, submission(2, 1, 0))).await;
+    assert_eq!(res["reason"], "ERR_RATE_LIMIT");
+    let res: Value = test::read_body_json(submit!(app, submission(0, 1, 0))).await;
+    assert_eq!(res["reason"], "ERR_INVALID_ARGUMENT");
+
+    let req = test::TestRequest::get()
+        .uri("/contests/1/ranklist")
+        .to_request();
+    let ranklist: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ranklist.as_array().unwrap().len(), 2);
+    assert_eq!(ranklist[0]["team"]["name"], "ab");
+    assert_eq!(ranklist[0]["user"], Value::Null);
+    assert_eq!(ranklist[0]["scores"], json!([0.0]));
+    assert_eq!(ranklist[1]["team"]["name"], "c");
+
+    // Members of a registered team are locked, a rename is still fine
+    let req = test::TestRequest::post()
+        .uri("/teams")
+        .set_json(json!({ "id": 1, "name": "c", "user_ids": [3, 1] }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::post()
+        .uri("/teams")
+        .set_json(json!({ "id": 1, "name": "cc", "user_ids": [3] }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::post()
+        .uri("/teams")
+        .set_json(json!({ "id": 2, "name": "a", "user_ids": [1, 3] }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+}
+
+#[actix_web::test]
+async fn test_team_invites() {
+    let app = test::init_service(
+        App::new()
+            .app_data(new_state_with(|_| {}))
+            .configure(oj::routes),
+    )
+    .await;
+    let mut tokens = vec![];
+    for name in ["alice", "bob", "carol"] {
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "name": name, "password": "pw" }))
+            .to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        tokens.push(login!(app, name, "pw"));
+    }
+    let post = |token: &str, team: Value| {
+        test::TestRequest::post()
+            .uri("/teams")
+            .insert_header(("Authorization", token))
+            .set_json(team)
+            .to_request()
+    };
+    let (alice, bob, carol) = (&tokens[0], &tokens[1], &tokens[2]);
+
+    // Founders start alone and can't add anyone else
+    let req = post(alice, json!({ "name": "ab", "user_ids": [1, 2] }));
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = post(alice, json!({ "name": "ab", "user_ids": [1] }));
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = post(alice, json!({ "id": 0, "name": "ab", "user_ids": [1, 2] }));
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = post(
+        alice,
+        json!({ "id": 0, "name": "ab", "user_ids": [1], "invited": [2] }),
+    );
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // Only the invited user can join, and only themselves
+    let req = post(carol, json!({ "id": 0, "name": "ab", "user_ids": [1, 3] }));
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = post(bob, json!({ "id": 0, "name": "ab", "user_ids": [1, 2, 3] }));
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = post(bob, json!({ "id": 0, "name": "ab", "user_ids": [1, 2] }));
+    let team: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(team["user_ids"], json!([1, 2]));
+    assert_eq!(team["invited"], json!([]));
+}
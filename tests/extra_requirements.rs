@@ -0,0 +1,56 @@
+mod common;
+use common::{build_and_find_path, TestCase};
+use std::process::{Child, Command, Stdio};
+
+#[test]
+fn test_ext_01_parallel_cases() {
+    // cases of one job are judged concurrently, packing groups stay sequential
+    // check that results come back in the original case order
+    TestCase::read("ext_01_parallel_cases").run();
+}
+
+struct Workers(Vec<Child>);
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        for worker in self.0.iter_mut() {
+            worker.kill().ok();
+        }
+    }
+}
+
+#[test]
+fn test_ext_02_remote_workers() {
+    // the server judges nothing itself (local_workers = 0)
+    // two judge-worker processes pull the jobs over HTTP
+    let path = build_and_find_path("judge-worker");
+    let _workers = Workers(
+        (0..2)
+            .map(|i| {
+                Command::new(&path)
+                    .args(["--server", "http://127.0.0.1:12345"])
+                    .args(["--name", &format!("worker-{}", i)])
+                    .args(["--heartbeat", "1"])
+                    .args(["--token", "worker-secret"])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect(),
+    );
+    TestCase::read("ext_02_remote_workers").run();
+}
+
+#[test]
+fn test_ext_03_bulk_rejudge() {
+    // POST /rejudge queues every matching job and returns a pollable batch
+    // the report lists old and new verdicts of each job
+    TestCase::read("ext_03_bulk_rejudge").run();
+}
+
+#[test]
+fn test_ext_04_system_error() {
+    // a missing input file must finish the job with System Error instead of leaving it queued
+    TestCase::read("ext_04_system_error").run();
+}
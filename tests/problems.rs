@@ -0,0 +1,524 @@
+mod support;
+
+use actix_web::{test, App};
+use serde_json::{json, Value};
+use support::{new_state_with, submission, submit, WORKER};
+
+#[actix_web::test]
+async fn test_problems() {
+    let dir = tempdir::TempDir::new("oj-problems").unwrap();
+    let (input, answer) = (dir.path().join("1.in"), dir.path().join("1.ans"));
+    std::fs::write(&input, "1 2\n").unwrap();
+    std::fs::write(&answer, "3\n").unwrap();
+    let storage = dir.path().join("data").to_str().unwrap().to_string();
+    let data_dir = dir.path().to_str().unwrap().to_string();
+    let conf = |storage: &str| {
+        let (storage, data_dir) = (storage.to_string(), data_dir.clone());
+        new_state_with(move |conf| {
+            conf["server"]["admin_token"] = json!("secret");
+            conf["storage"] = json!({ "type": "file", "path": storage });
+            conf["data_dir"] = json!(data_dir);
+        })
+    };
+    let app = test::init_service(App::new().app_data(conf(&storage)).configure(oj::routes)).await;
+    let admin = ("Authorization", "Bearer secret");
+
+    let req = test::TestRequest::get().uri("/problems").to_request();
+    let problems: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(problems.as_array().unwrap().len(), 1);
+    assert_eq!(problems[0]["name"], "a_plus_b");
+    assert_eq!(problems[0]["cases"][0]["input_file"], Value::Null);
+    assert_eq!(problems[0]["misc"], Value::Null);
+
+    let mut problem = json!({
+        "name": "sum",
+        "type": "standard",
+        "misc": { "samples": [1] },
+        "cases": [{
+            "score": 100,
+            "input_file": input,
+            "answer_file": answer,
+            "time_limit": 1000000,
+            "memory_limit": 0
+        }]
+    });
+    let req = test::TestRequest::post()
+        .uri("/problems")
+        .set_json(&problem)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::post()
+        .uri("/problems")
+        .insert_header(admin)
+        .set_json(&problem)
+        .to_request();
+    let created: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(created["id"], 1);
+    let req = test::TestRequest::post()
+        .uri("/problems")
+        .insert_header(admin)
+        .set_json({
+            let mut bad = problem.clone();
+            bad["misc"]["samples"] = json!([2]);
+            bad
+        })
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    // Only files of the data directory or the config can be cases
+    for file in ["/etc/passwd", &format!("{}/../1.in", data_dir)] {
+        let req = test::TestRequest::post()
+            .uri("/problems")
+            .insert_header(admin)
+            .set_json({
+                let mut bad = problem.clone();
+                bad["cases"][0]["input_file"] = json!(file);
+                bad
+            })
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    let req = test::TestRequest::get().uri("/problems/1").to_request();
+    let view: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        view["samples"],
+        json!([{ "id": 1, "input": "1 2\n", "answer": "3\n" }])
+    );
+    assert_eq!(view["cases"][0]["time_limit"], 1000000);
+    let req = test::TestRequest::get()
+        .uri("/contests/0")
+        .insert_header(admin)
+        .to_request();
+    let contest: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(contest["problem_ids"], json!([0, 1]));
+
+    problem["name"] = json!("sum2");
+    let req = test::TestRequest::put()
+        .uri("/problems/1")
+        .insert_header(admin)
+        .set_json(&problem)
+        .to_request();
+    let updated: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(updated["name"], "sum2");
+    let req = test::TestRequest::post()
+        .uri("/problems")
+        .insert_header(admin)
+        .set_json(&problem)
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    assert!(submit!(app, submission(0, 0, 1), admin)
+        .status()
+        .is_success());
+    // Not while the job waits to be judged
+    let req = test::TestRequest::put()
+        .uri("/problems/1")
+        .insert_header(admin)
+        .set_json(&problem)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::delete()
+        .uri("/problems/1")
+        .insert_header(admin)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::delete()
+        .uri("/problems/2")
+        .insert_header(admin)
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // Changes survive a restart
+    drop(app);
+    let app = test::init_service(App::new().app_data(conf(&storage)).configure(oj::routes)).await;
+    let req = test::TestRequest::get().uri("/problems").to_request();
+    let problems: Value = test::call_and_read_body_json(&app, req).await;
+    let names: Vec<_> = problems
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].clone())
+        .collect();
+    assert_eq!(names, ["a_plus_b", "sum2"]);
+}
+
+fn tarball(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(vec![]);
+    for (name, content) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, name, content.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[actix_web::test]
+async fn test_test_data() {
+    let dir = tempdir::TempDir::new("oj-data").unwrap();
+    let data_dir = dir.path().to_str().unwrap().to_string();
+    let state = new_state_with(|conf| {
+        conf["server"]["admin_token"] = json!("secret");
+        conf["data_dir"] = json!(data_dir);
+    });
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let admin = ("Authorization", "Bearer secret");
+
+    let body = tarball(&[
+        ("10.in", "10\n"),
+        ("10.ans", "100\n"),
+        ("2.in", "2\n"),
+        ("2.ans", "4\n"),
+        ("1.in", "1\n"),
+        ("1.ans", "1\n"),
+    ]);
+    let req = test::TestRequest::post()
+        .uri("/problems/0/data")
+        .set_payload(body.clone())
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::post()
+        .uri("/problems/0/data")
+        .insert_header(admin)
+        .set_payload(body)
+        .to_request();
+    let cases: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(cases.as_array().unwrap().len(), 3);
+    // "2" sorts before "10"
+    assert_eq!(cases[1]["input_size"], 2);
+    assert_eq!(cases[2]["answer_size"], 4);
+    assert_eq!(
+        cases[0]["answer_sha256"],
+        "4355a46b19d348dc2f57c046f8ef63d4538ebb936000f3c9ee954a27460dd865"
+    );
+    let input = cases[0]["input_file"].as_str().unwrap();
+    assert!(input.starts_with(&data_dir));
+    assert_eq!(std::fs::read_to_string(input).unwrap(), "1\n");
+
+    let req = test::TestRequest::post()
+        .uri("/problems/0/data")
+        .insert_header(admin)
+        .set_payload(tarball(&[("3.in", "3\n")]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::post()
+        .uri("/problems/0/data?append=true")
+        .insert_header(admin)
+        .set_payload(tarball(&[("3.in", "3\n"), ("3.ans", "9\n")]))
+        .to_request();
+    let cases: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(cases.as_array().unwrap().len(), 4);
+
+    assert!(submit!(app, submission(0, 0, 0), admin)
+        .status()
+        .is_success());
+    // A queued job reads the paths when it is handed out, a running one has them
+    let req = test::TestRequest::post()
+        .uri("/internal/workers/test/fetch")
+        .insert_header(WORKER)
+        .to_request();
+    let task: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(task["problem"]["cases"][0]["input_file"], input);
+    let req = test::TestRequest::put()
+        .uri("/problems/0/data/1")
+        .insert_header(admin)
+        .set_json(json!({ "input": "5\n", "answer": "25\n" }))
+        .to_request();
+    let cases: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(cases[0]["answer_size"], 3);
+    assert!(std::path::Path::new(input).exists());
+    let case =
+        |id, result| json!({ "id": id, "result": result, "time": 0, "memory": 0, "info": "" });
+    let req = test::TestRequest::post()
+        .uri(&format!(
+            "/internal/workers/test/jobs/{}/report",
+            task["job_id"]
+        ))
+        .insert_header(WORKER)
+        .set_json(json!([
+            case(0, "Compilation Success"),
+            case(1, "Accepted"),
+            case(2, "Accepted"),
+            case(3, "Accepted"),
+            case(4, "Accepted")
+        ]))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    assert!(!std::path::Path::new(input).exists());
+
+    let req = test::TestRequest::post()
+        .uri("/problems/0/data/order")
+        .insert_header(admin)
+        .set_json(json!([4, 3, 2]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::post()
+        .uri("/problems/0/data/order")
+        .insert_header(admin)
+        .set_json(json!([4, 3, 2, 1]))
+        .to_request();
+    let reordered: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(reordered[0]["input_sha256"], cases[3]["input_sha256"]);
+    assert_eq!(reordered[3]["id"], 4);
+
+    let req = test::TestRequest::delete()
+        .uri("/problems/0/data/2")
+        .insert_header(admin)
+        .to_request();
+    let cases: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(cases.as_array().unwrap().len(), 3);
+    let req = test::TestRequest::get()
+        .uri("/problems/0/data")
+        .insert_header(admin)
+        .to_request();
+    assert_eq!(
+        test::call_and_read_body_json::<_, _, Value>(&app, req).await,
+        cases
+    );
+}
+
+#[actix_web::test]
+async fn test_import() {
+    let dir = tempdir::TempDir::new("oj-import").unwrap();
+    let package = dir.path().join("package");
+    std::fs::create_dir_all(package.join("data")).unwrap();
+    std::fs::create_dir_all(package.join("statement")).unwrap();
+    for i in [1, 2, 10] {
+        let data = package.join("data");
+        std::fs::write(data.join(format!("{}.in", i)), format!("{}\n", i)).unwrap();
+        std::fs::write(data.join(format!("{}.ans", i)), format!("{}\n", i * i)).unwrap();
+    }
+    std::fs::write(package.join("check.sh"), "#!/bin/sh\n").unwrap();
+    std::fs::write(package.join("statement/statement.md"), "# Square\n").unwrap();
+    std::fs::write(
+        package.join("problem.yaml"),
+        "name: square\ntype: spj\ntime_limit: 2000000\nscore: 90\nsamples: [1]\nchecker:\n  file: check.sh\n",
+    )
+    .unwrap();
+    let data_dir = dir.path().join("problems").to_str().unwrap().to_string();
+    let state = new_state_with(|conf| {
+        conf["server"]["admin_token"] = json!("secret");
+        conf["data_dir"] = json!(data_dir);
+    });
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let admin = ("Authorization", "Bearer secret");
+
+    let body = json!({ "path": package });
+    let req = test::TestRequest::post()
+        .uri("/problems/import")
+        .set_json(&body)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::post()
+        .uri("/problems/import")
+        .insert_header(admin)
+        .set_json(&body)
+        .to_request();
+    let prob: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(prob["id"], 1);
+    assert_eq!(prob["type"], "spj");
+    assert_eq!(prob["cases"].as_array().unwrap().len(), 3);
+    assert_eq!(prob["cases"][0]["score"], 30.0);
+    assert_eq!(prob["cases"][0]["time_limit"], 2000000);
+    // "2" comes before "10"
+    let answer = prob["cases"][2]["answer_file"].as_str().unwrap();
+    assert_eq!(std::fs::read_to_string(answer).unwrap(), "100\n");
+    let checker = &prob["misc"]["special_judge"];
+    assert_eq!(checker[1], "%OUTPUT%");
+    assert!(std::path::Path::new(checker[0].as_str().unwrap()).exists());
+    assert!(std::path::Path::new(&data_dir)
+        .join("1/statement/statement.md")
+        .exists());
+
+    let req = test::TestRequest::get().uri("/problems/1").to_request();
+    let view: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(view["samples"][0]["answer"], "1\n");
+    let req = test::TestRequest::get()
+        .uri("/contests/0")
+        .insert_header(admin)
+        .to_request();
+    let contest: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(contest["problem_ids"], json!([0, 1]));
+
+    // A json manifest replaces the problem, data without an answer is refused
+    std::fs::remove_file(package.join("problem.yaml")).unwrap();
+    std::fs::write(package.join("problem.json"), r#"{ "name": "square2" }"#).unwrap();
+    let body = json!({ "path": package, "id": 1 });
+    let req = test::TestRequest::post()
+        .uri("/problems/import")
+        .insert_header(admin)
+        .set_json(&body)
+        .to_request();
+    let prob: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(prob["name"], "square2");
+    assert_eq!(prob["type"], "standard");
+    assert!(!std::path::Path::new(checker[0].as_str().unwrap()).exists());
+    std::fs::remove_file(package.join("data/2.ans")).unwrap();
+    let req = test::TestRequest::post()
+        .uri("/problems/import")
+        .insert_header(admin)
+        .set_json(&body)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    std::fs::write(package.join("data/2.ans"), "4\n").unwrap();
+
+    // A checker only goes with a special judge
+    std::fs::write(
+        package.join("problem.json"),
+        r#"{ "name": "square3", "type": "strict", "checker": { "file": "check.sh" } }"#,
+    )
+    .unwrap();
+    let req = test::TestRequest::post()
+        .uri("/problems/import")
+        .insert_header(admin)
+        .set_json(&body)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // Problems with jobs are not replaced
+    std::fs::write(package.join("problem.json"), r#"{ "name": "square3" }"#).unwrap();
+    assert!(submit!(app, submission(0, 0, 1), admin)
+        .status()
+        .is_success());
+    let req = test::TestRequest::post()
+        .uri("/problems/import")
+        .insert_header(admin)
+        .set_json(&body)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_statement() {
+    let dir = tempdir::TempDir::new("oj-statement").unwrap();
+    let package = dir.path().join("package");
+    std::fs::create_dir_all(package.join("data")).unwrap();
+    std::fs::create_dir_all(package.join("statement")).unwrap();
+    std::fs::write(package.join("data/1.in"), "1 < 2\n").unwrap();
+    std::fs::write(package.join("data/1.ans"), "3\n").unwrap();
+    std::fs::write(package.join("problem.yaml"), "name: a<b\nsamples: [1]\n").unwrap();
+    std::fs::write(
+        package.join("statement/legend.md"),
+        "Compute $a_1 + b_1$.\n\n![figure](fig.png)\n",
+    )
+    .unwrap();
+    std::fs::write(package.join("statement/input.md"), "Two *integers*.\n").unwrap();
+    std::fs::write(package.join("statement/fig.png"), "png").unwrap();
+    let data_dir = dir.path().join("problems").to_str().unwrap().to_string();
+    let state = new_state_with(|conf| {
+        conf["server"]["admin_token"] = json!("secret");
+        conf["data_dir"] = json!(data_dir);
+    });
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let admin = ("Authorization", "Bearer secret");
+
+    let req = test::TestRequest::get()
+        .uri("/problems/0/statement")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let req = test::TestRequest::post()
+        .uri("/problems/import")
+        .insert_header(admin)
+        .set_json(json!({ "path": package }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let req = test::TestRequest::get()
+        .uri("/problems/1/statement")
+        .to_request();
+    let statement: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(statement["name"], "a<b");
+    assert_eq!(statement["input_format"], "Two *integers*.\n");
+    assert_eq!(statement["output_format"], "");
+    assert_eq!(
+        statement["samples"],
+        json!([{ "id": 1, "input": "1 < 2\n", "answer": "3\n" }])
+    );
+    assert_eq!(statement["images"], json!(["fig.png"]));
+
+    let req = test::TestRequest::get()
+        .uri("/problems/1/statement?format=html")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "text/html; charset=utf-8"
+    );
+    let html = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(html.contains("<h1>a&lt;b</h1>"));
+    assert!(html.contains(r#"<span class="math math-inline">a_1 + b_1</span>"#));
+    assert!(html.contains(r#"src="/problems/1/statement/fig.png""#));
+    assert!(html.contains("<em>integers</em>"));
+    assert!(html.contains(r#"<pre class="input">1 &lt; 2"#));
+    assert!(!html.contains("<h2>Notes</h2>"));
+
+    let req = test::TestRequest::get()
+        .uri("/problems/1/statement/fig.png")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("content-type").unwrap(), "image/png");
+    assert_eq!(test::read_body(resp).await, "png");
+
+    let req = test::TestRequest::put()
+        .uri("/problems/1/statement/graph.svg")
+        .set_payload("<svg/>")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::put()
+        .uri("/problems/1/statement/.hidden")
+        .insert_header(admin)
+        .set_payload("<svg/>")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    let req = test::TestRequest::put()
+        .uri("/problems/1/statement/graph.svg")
+        .insert_header(admin)
+        .set_payload("<svg/>")
+        .to_request();
+    let images: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(images, json!(["fig.png", "graph.svg"]));
+    let req = test::TestRequest::get()
+        .uri("/problems/1/statement/graph.svg")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment"
+    );
+    assert!(resp.headers().contains_key("content-security-policy"));
+    let req = test::TestRequest::delete()
+        .uri("/problems/1/statement/fig.png")
+        .insert_header(admin)
+        .to_request();
+    let images: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(images, json!(["graph.svg"]));
+
+    let req = test::TestRequest::put()
+        .uri("/problems/1/statement")
+        .insert_header(admin)
+        .set_json(json!({
+            "legend": "New <script>alert(1)</script> [x](javascript:alert(1))",
+            "notes": "See $$x^2$$."
+        }))
+        .to_request();
+    let statement: Value = test::call_and_read_body_json(&app, req).await;
+    assert!(statement["legend"].as_str().unwrap().starts_with("New"));
+    assert_eq!(statement["input_format"], "");
+    let req = test::TestRequest::get()
+        .uri("/problems/1/statement?format=html")
+        .to_request();
+    let html = test::read_body(test::call_service(&app, req).await).await;
+    let html = String::from_utf8(html.to_vec()).unwrap();
+    assert!(html.contains(r#"<h2>Notes</h2>"#));
+    assert!(html.contains(r#"<span class="math math-display">x^2</span>"#));
+    assert!(!html.contains("<script>"));
+    assert!(html.contains("&lt;script&gt;"));
+    assert!(!html.contains("javascript:"));
+    let req = test::TestRequest::get()
+        .uri("/problems/1/statement?format=pdf")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
@@ -0,0 +1,62 @@
+mod support;
+
+use actix_web::{test, App};
+use serde_json::{json, Value};
+use support::{judge_next, new_state_with, submission, submit};
+
+#[actix_web::test]
+async fn test_rejudge_report() {
+    let dir = tempdir::TempDir::new("oj-rejudge").unwrap();
+    let storage = dir.path().to_str().unwrap().to_string();
+    let conf = |language: &str| {
+        let (storage, language) = (storage.clone(), language.to_string());
+        new_state_with(move |conf| {
+            conf["server"]["admin_token"] = json!("secret");
+            conf["storage"] = json!({ "type": "file", "path": storage });
+            conf["languages"][0]["name"] = json!(language);
+        })
+    };
+    let admin = ("Authorization", "Bearer secret");
+    let app = test::init_service(App::new().app_data(conf("Rust")).configure(oj::routes)).await;
+    assert!(submit!(app, submission(0, 0, 0), admin)
+        .status()
+        .is_success());
+    judge_next!(app, "Accepted");
+
+    // The language is gone after a restart, the dry run still finishes
+    drop(app);
+    let app = test::init_service(App::new().app_data(conf("C")).configure(oj::routes)).await;
+    let req = test::TestRequest::post()
+        .uri("/rejudge")
+        .insert_header(admin)
+        .set_json(json!({ "dry_run": true }))
+        .to_request();
+    let rejudge: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(rejudge["total"], 1);
+    let req = test::TestRequest::get().uri("/rejudge/0").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::get()
+        .uri("/rejudge/0/report")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let mut rejudge = rejudge;
+    for _ in 0..50 {
+        if rejudge["state"] == "Finished" {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let req = test::TestRequest::get()
+            .uri("/rejudge/0")
+            .insert_header(admin)
+            .to_request();
+        rejudge = test::call_and_read_body_json(&app, req).await;
+    }
+    assert_eq!(rejudge["state"], "Finished");
+    let req = test::TestRequest::get()
+        .uri("/rejudge/0/report")
+        .insert_header(admin)
+        .to_request();
+    let report: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(report[0]["old_result"], "Accepted");
+    assert_eq!(report[0]["new_result"], "System Error");
+}
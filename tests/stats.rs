@@ -0,0 +1,113 @@
+mod support;
+
+use actix_web::{test, App};
+use serde_json::{json, Value};
+use support::{judge_next, new_open_state, new_state_with, submission, submit, time};
+
+#[actix_web::test]
+async fn test_user_stats() {
+    let app = test::init_service(App::new().app_data(new_open_state()).configure(oj::routes)).await;
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(json!({ "name": "alice" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::post()
+        .uri("/contests")
+        .set_json(json!({
+            "name": "round",
+            "from": time(-60),
+            "to": time(60),
+            "problem_ids": [0],
+            "user_ids": [0, 1],
+            "submission_limit": 10
+        }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    for (contest_id, result) in [(0, Some("Accepted")), (1, Some("Wrong Answer")), (1, None)] {
+        assert!(submit!(app, submission(1, contest_id, 0))
+            .status()
+            .is_success());
+        if let Some(result) = result {
+            judge_next!(app, result);
+        }
+    }
+
+    let req = test::TestRequest::get().uri("/users/1/stats").to_request();
+    let stats: Value = test::call_and_read_body_json(&app, req).await;
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    assert_eq!(stats["user"]["name"], "alice");
+    assert_eq!(stats["submissions"], 3);
+    assert_eq!(stats["solved"], json!([0]));
+    assert_eq!(stats["attempted"], json!([0]));
+    assert_eq!(
+        stats["verdicts"],
+        json!({ "Accepted": 1, "Wrong Answer": 1 })
+    );
+    assert_eq!(stats["languages"], json!({ "Rust": 3 }));
+    assert_eq!(stats["heatmap"], json!({ today: 3 }));
+    assert_eq!(
+        stats["contests"],
+        json!([{ "contest_id": 1, "name": "round", "rank": 1, "participants": 2 }])
+    );
+    let req = test::TestRequest::get().uri("/users/9/stats").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+}
+
+#[actix_web::test]
+async fn test_problem_stats() {
+    let state = new_state_with(|conf| conf["server"]["admin_token"] = json!("secret"));
+    let app = test::init_service(App::new().app_data(state).configure(oj::routes)).await;
+    let admin = ("Authorization", "Bearer secret");
+    let req = test::TestRequest::post()
+        .uri("/users")
+        .set_json(json!({ "name": "alice" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    for (user_id, result) in [
+        (0, Some("Accepted")),
+        (1, Some("Wrong Answer")),
+        (1, Some("Accepted")),
+        (1, None),
+    ] {
+        assert!(submit!(app, submission(user_id, 0, 0), admin)
+            .status()
+            .is_success());
+        if let Some(result) = result {
+            judge_next!(app, result);
+        }
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/problems/0/stats")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+    let req = test::TestRequest::get()
+        .uri("/problems/0/stats")
+        .insert_header(admin)
+        .to_request();
+    let stats: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(stats["submissions"], 4);
+    assert_eq!(stats["accepted_users"], 2);
+    assert_eq!(stats["acceptance_rate"], 2.0 / 3.0);
+    assert_eq!(
+        stats["verdicts"],
+        json!({ "Accepted": 2, "Wrong Answer": 1 })
+    );
+    assert_eq!(
+        stats["cases"],
+        json!([{ "id": 1, "judged": 3, "failed": 1 }])
+    );
+    assert_eq!(
+        stats["fastest"],
+        json!({ "Rust": [
+            { "job_id": 0, "user_id": 0, "time": 0 },
+            { "job_id": 2, "user_id": 1, "time": 0 }
+        ] })
+    );
+    let req = test::TestRequest::get()
+        .uri("/problems/7/stats")
+        .insert_header(admin)
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+}
@@ -0,0 +1,101 @@
+// Shared by the in-process tests, each of them uses only some of it
+#![allow(dead_code, unused_macros)]
+
+use actix_web::web;
+use oj::{conf::Conf, state::AppState};
+use serde_json::{json, Value};
+
+pub const WORKER: (&str, &str) = ("Authorization", "Bearer worker");
+
+// Stand in for a judge-worker: take the next job and report one verdict for it
+macro_rules! judge_next {
+    ($app: expr, $result: expr) => {{
+        let req = actix_web::test::TestRequest::post()
+            .uri("/internal/workers/test/fetch")
+            .insert_header($crate::support::WORKER)
+            .to_request();
+        let task: serde_json::Value = actix_web::test::call_and_read_body_json(&$app, req).await;
+        let case = |id, result| serde_json::json!({ "id": id, "result": result, "time": 0, "memory": 0, "info": "" });
+        let req = actix_web::test::TestRequest::post()
+            .uri(&format!("/internal/workers/test/jobs/{}/report", task["job_id"]))
+            .insert_header($crate::support::WORKER)
+            .set_json(serde_json::json!([case(0, "Compilation Success"), case(1, $result)]))
+            .to_request();
+        assert!(actix_web::test::call_service(&$app, req).await.status().is_success());
+    }};
+}
+
+// POST /jobs with the given headers, evaluates to the response
+macro_rules! submit {
+    ($app: expr, $job: expr $(, $header: expr)*) => {{
+        let req = actix_web::test::TestRequest::post()
+            .uri("/jobs")
+            $(.insert_header($header))*
+            .set_json(&$job)
+            .to_request();
+        actix_web::test::call_service(&$app, req).await
+    }};
+}
+
+// Logs in, evaluates to the value of the Authorization header
+macro_rules! login {
+    ($app: expr, $name: expr, $password: expr) => {{
+        let req = actix_web::test::TestRequest::post()
+            .uri("/login")
+            .set_json(serde_json::json!({ "name": $name, "password": $password }))
+            .to_request();
+        let session: serde_json::Value = actix_web::test::call_and_read_body_json(&$app, req).await;
+        format!("Bearer {}", session["token"].as_str().unwrap())
+    }};
+}
+
+#[allow(unused_imports)]
+pub(crate) use {judge_next, login, submit};
+
+// An empty program in the stub language, nothing compiles it
+pub fn submission(user_id: i32, contest_id: i32, problem_id: i32) -> Value {
+    json!({
+        "source_code": "",
+        "language": "Rust",
+        "user_id": user_id,
+        "contest_id": contest_id,
+        "problem_id": problem_id
+    })
+}
+
+// Anonymous requests act as admin, like in the HTTP test cases
+pub fn new_open_state() -> web::Data<AppState> {
+    new_state_with(|conf| conf["server"]["open"] = json!(true))
+}
+
+pub fn new_state_with(edit: impl FnOnce(&mut Value)) -> web::Data<AppState> {
+    web::Data::new(AppState::new(conf_with(edit)).unwrap())
+}
+
+pub fn conf_with(edit: impl FnOnce(&mut Value)) -> Conf {
+    let mut conf = json!({
+        "server": {},
+        "judge": { "local_workers": 0, "worker_token": "worker" },
+        "storage": { "type": "memory" },
+        "problems": [{
+            "id": 0,
+            "name": "a_plus_b",
+            "type": "standard",
+            "cases": [{
+                "score": 100,
+                "input_file": "1.in",
+                "answer_file": "1.ans",
+                "time_limit": 1000000,
+                "memory_limit": 0
+            }]
+        }],
+        "languages": [{ "name": "Rust", "file_name": "main.rs", "command": [] }]
+    });
+    edit(&mut conf);
+    serde_json::from_value(conf).unwrap()
+}
+
+pub fn time(minutes: i64) -> String {
+    (chrono::Utc::now() + chrono::Duration::minutes(minutes))
+        .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}